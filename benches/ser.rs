@@ -0,0 +1,89 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Panel {
+    id: u32,
+    title: String,
+    query: Query,
+    options: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct Query {
+    language: &'static str,
+    filters: Vec<Filter>,
+}
+
+#[derive(Serialize)]
+struct Filter {
+    field: String,
+    op: &'static str,
+    value: Vec<i64>,
+    meta: Meta,
+}
+
+#[derive(Serialize)]
+struct Meta {
+    disabled: bool,
+    negate: bool,
+    alias: Option<String>,
+}
+
+/// A dashboard state with `panels` panels of a few filters each.
+fn dashboard(panels: u32) -> Vec<Panel> {
+    (0..panels)
+        .map(|id| Panel {
+            id,
+            title: format!("panel {}", id),
+            query: Query {
+                language: "kuery",
+                filters: (0..4)
+                    .map(|i| Filter {
+                        field: format!("field_{}", i),
+                        op: "is",
+                        value: vec![i, -i, 1 << i],
+                        meta: Meta {
+                            disabled: false,
+                            negate: i % 2 == 0,
+                            alias: None,
+                        },
+                    })
+                    .collect(),
+            },
+            options: (0..8)
+                .map(|i| (format!("opt{}", i), format!("value '{}'", i)))
+                .collect(),
+        })
+        .collect()
+}
+
+/// A chain of single-key maps, `depth` levels deep.
+fn nested(depth: usize) -> serde_rison::Value {
+    let mut value = serde_rison::Value::from("leaf");
+    for i in 0..depth {
+        value = serde_rison::Value::from_iter([(format!("k{}", i), value)]);
+    }
+    value
+}
+
+fn bench_ser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_string");
+    for panels in [10, 100] {
+        let state = dashboard(panels);
+        group.bench_with_input(BenchmarkId::new("dashboard", panels), &state, |b, state| {
+            b.iter(|| serde_rison::ser::to_string(state).unwrap())
+        });
+    }
+    for depth in [16, 128] {
+        let value = nested(depth);
+        group.bench_with_input(BenchmarkId::new("nested", depth), &value, |b, value| {
+            b.iter(|| serde_rison::ser::to_string(value).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ser);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+//! Compile-time checked rison literals.
+//!
+//! ```
+//! use serde_rison_macros::{rison_lit, rison_value};
+//!
+//! const DEFAULT_STATE: &str = rison_lit!("(a:!(1,2),b:'x y')");
+//! assert_eq!(DEFAULT_STATE, "(a:!(1,2),b:'x y')");
+//!
+//! let value = rison_value!("(a:!(1,2),b:'x y')");
+//! assert_eq!(value["b"], serde_rison::Value::from("x y"));
+//! ```
+//!
+//! A literal that is not valid rison fails to compile, with the parser's
+//! error reported at the literal:
+//!
+//! ```compile_fail
+//! const BROKEN: &str = serde_rison_macros::rison_lit!("(a:!(1,2)");
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::quote;
+use serde_rison::Value;
+use syn::{parse_macro_input, LitStr};
+
+/// Checks that a string literal is valid rison and expands to the literal
+/// itself, a `&'static str`.
+#[proc_macro]
+pub fn rison_lit(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    match parse(&lit) {
+        Ok(_) => quote!(#lit).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Parses a rison string literal at compile time and expands to an
+/// expression constructing the equivalent `serde_rison::Value`.
+#[proc_macro]
+pub fn rison_value(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    match parse(&lit) {
+        Ok(value) => to_tokens(&value).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn parse(lit: &LitStr) -> syn::Result<Value> {
+    serde_rison::de::from_str(&lit.value())
+        .map_err(|err| syn::Error::new(lit.span(), format!("invalid rison: {}", err)))
+}
+
+fn to_tokens(value: &Value) -> TokenStream2 {
+    match value {
+        Value::Null => quote!(::serde_rison::Value::Null),
+        Value::Bool(b) => quote!(::serde_rison::Value::Bool(#b)),
+        Value::Number(n) => {
+            let n = if let Some(u) = n.as_u64() {
+                let u = Literal::u64_suffixed(u);
+                quote!(::serde_rison::Number::from(#u))
+            } else if let Some(i) = n.as_i64() {
+                let i = Literal::i64_suffixed(i);
+                quote!(::serde_rison::Number::from(#i))
+            } else {
+                // Parsed numbers are always finite.
+                let f = Literal::f64_suffixed(n.as_f64().unwrap());
+                quote!(::serde_rison::Number::from_f64(#f).unwrap())
+            };
+            quote!(::serde_rison::Value::Number(#n))
+        }
+        Value::String(s) => quote!(::serde_rison::Value::String(::std::string::String::from(#s))),
+        Value::Array(array) => {
+            let elems = array.iter().map(to_tokens);
+            quote!(::serde_rison::Value::Array(::std::vec![#(#elems),*]))
+        }
+        Value::Object(map) => {
+            let keys = map.keys();
+            let values = map.values().map(to_tokens);
+            quote! {
+                ::serde_rison::Value::Object(
+                    <::serde_rison::Map<::std::string::String, ::serde_rison::Value>
+                        as ::std::iter::FromIterator<_>>::from_iter([
+                        #((::std::string::String::from(#keys), #values)),*
+                    ])
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde_rison::{de::from_str, Value};
+use serde_rison_macros::{rison_lit, rison_value};
+
+const STATE: &str = rison_lit!("(a:!(1,2),b:'x y')");
+
+#[test]
+fn test_rison_lit() {
+    assert_eq!(STATE, "(a:!(1,2),b:'x y')");
+    assert_eq!(rison_lit!("!n"), "!n");
+    assert_eq!(rison_lit!(r"'it!'s'"), "'it!'s'");
+}
+
+#[test]
+fn test_rison_value() {
+    macro_rules! check {
+        ($s:literal) => {
+            assert_eq!(rison_value!($s), from_str::<Value>($s).unwrap(), $s);
+        };
+    }
+    check!("!n");
+    check!("!t");
+    check!("0");
+    check!("-12");
+    check!("1.5e-3");
+    check!("18446744073709551615");
+    check!("''");
+    check!("'it!'s'");
+    check!("!()");
+    check!("()");
+    check!("(a:!(1,-2,(b:!f)),'c d':!n)");
+}
+
+#[test]
+fn test_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,8 @@
+use serde_rison_macros::{rison_lit, rison_value};
+
+const UNBALANCED: &str = rison_lit!("(a:!(1,2)");
+
+fn main() {
+    let _ = rison_value!("(a:1,)");
+    let _ = rison_lit!(1);
+}
@@ -1,27 +1,223 @@
-use crate::read;
-use alloc::{boxed::Box, string::ToString};
-use core::{fmt, result};
-use serde::de::{self, Deserialize, Expected, Unexpected, Visitor};
+use crate::path::{Path, Segment};
+use crate::read::{self, Reference};
+use crate::ser::NOT_ID_START;
+use crate::value::MapKeyDeserializer;
+use crate::DuplicateKeys;
+use alloc::{
+    boxed::Box,
+    collections::BTreeSet,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, marker::PhantomData, mem, result, str};
+use serde::de::{self, Deserialize, DeserializeSeed, Expected, Unexpected, Visitor};
+#[cfg(feature = "std")]
+use std::io;
 
 pub struct Error {
     code: ErrorCode,
-    position: usize,
+    position: Option<usize>,
+    path: Path,
 }
 
-enum ErrorCode {
+pub(crate) enum ErrorCode {
     Message(Box<str>),
+    #[cfg(feature = "std")]
+    Io(io::Error),
     InvalidChar(u8, u8),
     InvalidEscape(u8),
     EofWhileParsingValue,
+    EofWhileParsingString,
+    EofWhileParsingList,
+    EofWhileParsingObject,
+    ExpectedListCommaOrEnd,
+    ExpectedObjectCommaOrEnd,
+    ExpectedSomeValue,
     InvalidNumber,
+    NumberOutOfRange,
+    InvalidUnicode,
+    InvalidPercentEncoding,
+    TrailingComma,
+    TrailingCharacters,
+    OrisonMustBeAMap,
+    ArisonMustBeASeq,
+    KeyMustBeAString,
+    DuplicateKey(Box<str>),
+    RecursionLimitExceeded,
+    StringTooLong,
+    TooManyElements,
+    InputTooLong,
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl Error {
+    #[cold]
+    pub(crate) fn syntax(code: ErrorCode, position: usize) -> Self {
+        Error {
+            code,
+            position: Some(position),
+            path: Path::default(),
+        }
+    }
+
+    /// Creates an error for a failure of the underlying reader, for use by
+    /// custom `read::Read` implementations.
+    #[cfg(feature = "std")]
+    #[cold]
+    pub fn io(error: io::Error) -> Self {
+        Error {
+            code: ErrorCode::Io(error),
+            position: None,
+            path: Path::default(),
+        }
+    }
+
+    /// The number of bytes of input consumed when the error happened, or
+    /// `None` if the error is not tied to the input, like the ones raised
+    /// while deserializing from a `Value`.
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// The path to the value that failed, like `panels[3].gridData.w`, or an
+    /// empty string if it was the top-level value.
+    pub fn path(&self) -> String {
+        self.path.to_string()
+    }
+
+    /// Records that the error happened inside the given element or entry.
+    pub(crate) fn at(mut self, segment: Segment) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    /// Categorizes the cause of this error.
+    pub fn classify(&self) -> Category {
+        match self.code {
+            ErrorCode::Message(_)
+            | ErrorCode::OrisonMustBeAMap
+            | ErrorCode::ArisonMustBeASeq
+            | ErrorCode::DuplicateKey(_) => Category::Data,
+            #[cfg(feature = "std")]
+            ErrorCode::Io(_) => Category::Io,
+            ErrorCode::EofWhileParsingValue
+            | ErrorCode::EofWhileParsingString
+            | ErrorCode::EofWhileParsingList
+            | ErrorCode::EofWhileParsingObject => Category::Eof,
+            ErrorCode::InvalidChar(..)
+            | ErrorCode::InvalidEscape(_)
+            | ErrorCode::ExpectedListCommaOrEnd
+            | ErrorCode::ExpectedObjectCommaOrEnd
+            | ErrorCode::ExpectedSomeValue
+            | ErrorCode::InvalidNumber
+            | ErrorCode::NumberOutOfRange
+            | ErrorCode::InvalidUnicode
+            | ErrorCode::InvalidPercentEncoding
+            | ErrorCode::TrailingComma
+            | ErrorCode::TrailingCharacters
+            | ErrorCode::KeyMustBeAString
+            | ErrorCode::RecursionLimitExceeded
+            | ErrorCode::StringTooLong
+            | ErrorCode::TooManyElements
+            | ErrorCode::InputTooLong => Category::Syntax,
+        }
+    }
+
+    /// Returns true if the error was caused by a failure to read bytes from
+    /// the underlying reader.
+    pub fn is_io(&self) -> bool {
+        self.classify() == Category::Io
+    }
+
+    /// Returns true if the input is not valid rison, or is over one of the
+    /// deserializer's limits.
+    pub fn is_syntax(&self) -> bool {
+        self.classify() == Category::Syntax
+    }
+
+    /// Returns true if the input is valid rison but does not match the type
+    /// being deserialized.
+    pub fn is_data(&self) -> bool {
+        self.classify() == Category::Data
+    }
+
+    /// Returns true if the input ended in the middle of a value.
+    pub fn is_eof(&self) -> bool {
+        self.classify() == Category::Eof
+    }
+
+    /// The limit the input is over, if that is what the error is about.
+    /// Such errors are also syntax errors.
+    pub fn limit(&self) -> Option<LimitKind> {
+        match self.code {
+            ErrorCode::RecursionLimitExceeded => Some(LimitKind::Depth),
+            ErrorCode::StringTooLong => Some(LimitKind::StringLen),
+            ErrorCode::TooManyElements => Some(LimitKind::CollectionLen),
+            ErrorCode::InputTooLong => Some(LimitKind::InputLen),
+            _ => None,
+        }
+    }
+
+    /// The kind of the underlying io error, if the error is an io error.
+    #[cfg(feature = "std")]
+    pub fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        match &self.code {
+            ErrorCode::Io(err) => Some(err.kind()),
+            _ => None,
+        }
+    }
+}
+
+/// Categorizes the cause of a `de::Error`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Category {
+    /// Failure to read bytes from the underlying reader.
+    Io,
+    /// Input that is not valid rison.
+    Syntax,
+    /// Valid rison that does not match the type being deserialized, or a
+    /// custom error raised by a `Deserialize` impl.
+    Data,
+    /// The input ended in the middle of a value.
+    Eof,
+}
+
+/// The deserializer limit an input is over, as returned by `Error::limit`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LimitKind {
+    /// Nested deeper than `Deserializer::max_depth`.
+    Depth,
+    /// A string or key longer than `Deserializer::max_string_len`.
+    StringLen,
+    /// A sequence or map longer than `Deserializer::max_collection_len`.
+    CollectionLen,
+    /// More input than `Deserializer::max_input_len`.
+    InputLen,
+}
+
+/// Converts to an `io::Error`, for use in `io::Read` or `io::Write`
+/// implementations. Io errors are unwrapped, unexpected ends of input become
+/// `UnexpectedEof` and everything else `InvalidData`.
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err.code {
+            ErrorCode::Io(err) => err,
+            _ => match err.classify() {
+                Category::Eof => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+                _ => io::Error::new(io::ErrorKind::InvalidData, err),
+            },
+        }
+    }
+}
+
 impl fmt::Display for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ErrorCode::Message(msg) => f.write_str(msg),
+            #[cfg(feature = "std")]
+            ErrorCode::Io(err) => fmt::Display::fmt(err, f),
             ErrorCode::InvalidChar(unexp, exp) => {
                 write!(f, "invalid char: {}, expected {}", unexp, exp)
             }
@@ -29,24 +225,62 @@ impl fmt::Display for ErrorCode {
                 write!(f, "invalid escape char: {}", unexp)
             }
             ErrorCode::EofWhileParsingValue => f.write_str("EOF while parsing a value"),
+            ErrorCode::EofWhileParsingString => f.write_str("EOF while parsing a string"),
+            ErrorCode::EofWhileParsingList => f.write_str("EOF while parsing a list"),
+            ErrorCode::EofWhileParsingObject => f.write_str("EOF while parsing an object"),
+            ErrorCode::ExpectedListCommaOrEnd => f.write_str("expected `,` or `)`"),
+            ErrorCode::ExpectedObjectCommaOrEnd => f.write_str("expected `,` or `)`"),
+            ErrorCode::ExpectedSomeValue => f.write_str("expected value"),
             ErrorCode::InvalidNumber => f.write_str("invalid number"),
+            ErrorCode::NumberOutOfRange => f.write_str("number out of range"),
+            ErrorCode::InvalidUnicode => f.write_str("invalid unicode in string"),
+            ErrorCode::InvalidPercentEncoding => f.write_str("invalid percent-encoding"),
+            ErrorCode::TrailingComma => f.write_str("trailing comma"),
+            ErrorCode::TrailingCharacters => f.write_str("trailing characters"),
+            ErrorCode::OrisonMustBeAMap => f.write_str("O-Rison value must be a struct or map"),
+            ErrorCode::ArisonMustBeASeq => f.write_str("A-Rison value must be a sequence"),
+            ErrorCode::KeyMustBeAString => f.write_str("key must be a string"),
+            ErrorCode::DuplicateKey(key) => write!(f, "duplicate key `{}`", key),
+            ErrorCode::RecursionLimitExceeded => f.write_str("recursion limit exceeded"),
+            ErrorCode::StringTooLong => f.write_str("string too long"),
+            ErrorCode::TooManyElements => f.write_str("too many elements"),
+            ErrorCode::InputTooLong => f.write_str("input too long"),
         }
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} at position {}", self.code, self.position)
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        // Errors that are not tied to the input, such as the ones raised while
+        // deserializing from a `Value`, have no position.
+        match self.position {
+            Some(position) => write!(f, "{} at position {}", self.code, position),
+            None => fmt::Display::fmt(&self.code, f),
+        }
     }
 }
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error({}, position: {})", self.code, self.position)
+        match self.position {
+            Some(position) => write!(f, "Error({}, position: {})", self.code, position),
+            None => write!(f, "Error({})", self.code),
+        }
     }
 }
 
-impl de::StdError for Error {}
+impl de::StdError for Error {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn de::StdError + 'static)> {
+        match &self.code {
+            ErrorCode::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl de::Error for Error {
     fn custom<T>(msg: T) -> Self
@@ -55,7 +289,8 @@ impl de::Error for Error {
     {
         Error {
             code: ErrorCode::Message(msg.to_string().into_boxed_str()),
-            position: 0,
+            position: None,
+            path: Path::default(),
         }
     }
 
@@ -70,26 +305,356 @@ impl de::Error for Error {
 
 pub struct Deserializer<R> {
     read: R,
+    scratch: Vec<u8>,
+    duplicate_keys: DuplicateKeys,
+    remaining_depth: usize,
+    max_string_len: usize,
+    max_collection_len: usize,
+    max_input_len: usize,
+    /// Set by `StreamDeserializer` before a top-level id, which ends at a
+    /// line break. Taken by the next string parsed.
+    single_line: bool,
+    ignore_whitespace: bool,
+    #[cfg(feature = "unbounded_depth")]
+    disable_recursion_limit: bool,
 }
 
 impl<R> Deserializer<R> {
     pub fn new(read: R) -> Self {
-        Deserializer { read }
+        Deserializer {
+            read,
+            scratch: Vec::new(),
+            duplicate_keys: DuplicateKeys::default(),
+            remaining_depth: 128,
+            max_string_len: usize::MAX,
+            max_collection_len: usize::MAX,
+            max_input_len: usize::MAX,
+            single_line: false,
+            ignore_whitespace: false,
+            #[cfg(feature = "unbounded_depth")]
+            disable_recursion_limit: false,
+        }
+    }
+
+    /// Ignores spaces, tabs and line breaks between tokens, for hand-written
+    /// and indented rison like rison-node's parser allows. Whitespace inside
+    /// quoted strings and ids is kept, but tabs and line breaks at the end of
+    /// an id are taken to be between tokens.
+    ///
+    /// Strict rison, without whitespace, is the default.
+    pub fn ignore_whitespace(mut self) -> Self {
+        self.ignore_whitespace = true;
+        self
+    }
+
+    /// Sets how deeply sequences and maps may be nested, 128 by default.
+    /// Deeper input is rejected before it can overflow the stack.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.remaining_depth = depth;
+        self
+    }
+
+    /// Lifts the depth limit, growing the stack on demand instead, for input
+    /// that is legitimately nested deeper than the call stack allows.
+    ///
+    /// Such input can still use unbounded memory, so untrusted input should
+    /// also get a `max_input_len`. A `Value` read this way has to be dropped
+    /// with `Value::drop_deep`, as dropping it the usual way recurses.
+    #[cfg(feature = "unbounded_depth")]
+    pub fn disable_recursion_limit(mut self) -> Self {
+        self.disable_recursion_limit = true;
+        self
+    }
+
+    /// Sets the maximum length in bytes of a string or key, after unescaping.
+    /// Unlimited by default.
+    pub fn max_string_len(mut self, len: usize) -> Self {
+        self.max_string_len = len;
+        self
+    }
+
+    /// Sets the maximum number of elements in a sequence or entries in a
+    /// map. Unlimited by default.
+    pub fn max_collection_len(mut self, len: usize) -> Self {
+        self.max_collection_len = len;
+        self
+    }
+
+    /// Sets the maximum number of bytes of input to read. Unlimited by
+    /// default.
+    pub fn max_input_len(mut self, len: usize) -> Self {
+        self.max_input_len = len;
+        self
+    }
+
+    /// Sets what to do with a key that appears twice in the same map.
+    ///
+    /// With a policy other than `LastWins`, map keys are always handed to
+    /// the `Deserialize` impl as owned strings, so maps with borrowed `&str`
+    /// keys cannot be used.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> Self {
+        self.duplicate_keys = policy;
+        self
     }
 }
 
 impl<'a> Deserializer<read::SliceRead<'a>> {
-    /// Creates a JSON deserializer from a `&[u8]`.
+    /// Creates a rison deserializer from a `&[u8]`.
     pub fn from_slice(bytes: &'a [u8]) -> Self {
         Deserializer::new(read::SliceRead::new(bytes))
     }
+
+    /// Creates a rison deserializer from a `&str`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &'a str) -> Self {
+        Deserializer::from_slice(s.as_bytes())
+    }
+}
+
+impl<'a> Deserializer<read::PercentDecodeRead<'a>> {
+    /// Creates a rison deserializer from a percent-encoded `&str`, decoding
+    /// `+` as a space.
+    pub fn from_url_encoded_str(s: &'a str) -> Self {
+        Deserializer::new(read::PercentDecodeRead::new(s.as_bytes()).plus_as_space(true))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Deserializer<read::IoRead<R>>
+where
+    R: io::Read,
+{
+    /// Creates a rison deserializer from an `io::Read`.
+    pub fn from_reader(reader: R) -> Self {
+        Deserializer::new(read::IoRead::new(reader))
+    }
+}
+
+/// Turns the reader's "string too long" error into the error for the limit
+/// that was hit. Both point just past the start of the string, wherever the
+/// reader gave up.
+fn str_limit_error(err: Error, code: ErrorCode, start: usize) -> Error {
+    match err.code {
+        ErrorCode::StringTooLong => Error::syntax(code, start + 1),
+        _ => err,
+    }
+}
+
+/// Whitespace trimmed from the end of ids when it is ignored; ids cannot
+/// contain spaces anyway.
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r')
+}
+
+fn trim_end_whitespace(mut bytes: &[u8]) -> &[u8] {
+    while let [rest @ .., b'\t' | b'\n' | b'\r'] = bytes {
+        bytes = rest;
+    }
+    bytes
+}
+
+#[inline]
+fn is_id_start(b: u8) -> bool {
+    !NOT_ID_START[b as usize]
+}
+
+enum ParserNumber {
+    F64(f64),
+    U64(u64),
+    I64(i64),
+    U128(u128),
+    I128(i128),
+}
+
+impl ParserNumber {
+    fn visit<'de, V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            ParserNumber::F64(x) => visitor.visit_f64(x),
+            ParserNumber::U64(x) => visitor.visit_u64(x),
+            ParserNumber::I64(x) => visitor.visit_i64(x),
+            ParserNumber::U128(x) => visitor.visit_u128(x),
+            ParserNumber::I128(x) => visitor.visit_i128(x),
+        }
+    }
+
+    /// Appends the number the way the serializer writes it.
+    fn push_to(&self, s: &mut String) {
+        match *self {
+            ParserNumber::F64(x) => s.push_str(ryu::Buffer::new().format_finite(x)),
+            ParserNumber::U64(x) => s.push_str(itoa::Buffer::new().format(x)),
+            ParserNumber::I64(x) => s.push_str(itoa::Buffer::new().format(x)),
+            ParserNumber::U128(x) => s.push_str(itoa::Buffer::new().format(x)),
+            ParserNumber::I128(x) => s.push_str(itoa::Buffer::new().format(x)),
+        }
+    }
+
+    fn invalid_type(self, exp: &dyn Expected) -> Error {
+        match self {
+            ParserNumber::F64(x) => de::Error::invalid_type(Unexpected::Float(x), exp),
+            ParserNumber::U64(x) => de::Error::invalid_type(Unexpected::Unsigned(x), exp),
+            ParserNumber::I64(x) => de::Error::invalid_type(Unexpected::Signed(x), exp),
+            ParserNumber::U128(_) | ParserNumber::I128(_) => {
+                de::Error::invalid_type(Unexpected::Other("128-bit integer"), exp)
+            }
+        }
+    }
 }
 
-impl<R: read::Read> Deserializer<R> {
+impl<'de, R: read::Read<'de>> Deserializer<R> {
+    /// Checks that the input has been fully consumed. Call it after
+    /// deserializing a value, to reject input like `!t garbage`; the
+    /// `from_*` functions do so already.
+    pub fn end(&mut self) -> Result<()> {
+        match self.peek()? {
+            Some(_) => Err(self.peek_error(ErrorCode::TrailingCharacters)),
+            None => Ok(()),
+        }
+    }
+
+    /// Turns the deserializer into an iterator over values of type `T`
+    /// separated by line breaks, as in a log of one rison value per line.
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter<T>(self) -> StreamDeserializer<'de, R, T>
+    where
+        T: Deserialize<'de>,
+    {
+        StreamDeserializer {
+            de: self,
+            offset: 0,
+            failed: false,
+            output: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+
+    /// Peeks at the next token, skipping whitespace before it if enabled.
+    #[inline]
+    fn peek(&mut self) -> Result<Option<u8>> {
+        if self.ignore_whitespace {
+            while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek_raw()? {
+                self.eat_char();
+            }
+        }
+        self.peek_raw()
+    }
+
+    /// Peeks at the next byte, without skipping whitespace, for use inside
+    /// a token.
+    #[inline]
+    fn peek_raw(&mut self) -> Result<Option<u8>> {
+        let peek = self.read.peek()?;
+        if peek.is_some() && self.read.position() >= self.max_input_len {
+            return Err(self.peek_error(ErrorCode::InputTooLong));
+        }
+        Ok(peek)
+    }
+
+    #[inline]
+    fn peek_or_null(&mut self) -> Result<u8> {
+        Ok(self.peek_raw()?.unwrap_or(b'\x00'))
+    }
+
+    #[inline]
+    fn next_char(&mut self) -> Result<Option<u8>> {
+        if self.read.position() >= self.max_input_len {
+            return match self.read.next()? {
+                Some(_) => Err(self.error(ErrorCode::InputTooLong)),
+                None => Ok(None),
+            };
+        }
+        self.read.next()
+    }
+
+    /// Consumes the next token, skipping whitespace before it if enabled.
+    #[inline]
+    fn next_token(&mut self) -> Result<Option<u8>> {
+        self.peek()?;
+        self.next_char()
+    }
+
+    /// Parses a nested sequence or map one level deeper, failing when the
+    /// depth limit is reached.
+    fn recurse<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        #[cfg(feature = "unbounded_depth")]
+        if self.disable_recursion_limit {
+            return crate::maybe_grow_stack(|| f(self));
+        }
+        if self.remaining_depth == 0 {
+            return Err(self.error(ErrorCode::RecursionLimitExceeded));
+        }
+        self.remaining_depth -= 1;
+        let ret = f(self);
+        self.remaining_depth += 1;
+        ret
+    }
+
+    /// The longest string that can start at `start`, and the error for
+    /// going past it: whichever of the string and input limits is tighter.
+    fn str_limit(&self, start: usize) -> (usize, ErrorCode) {
+        let input_left = self.max_input_len.saturating_sub(start);
+        if self.max_string_len <= input_left {
+            (self.max_string_len, ErrorCode::StringTooLong)
+        } else {
+            (input_left, ErrorCode::InputTooLong)
+        }
+    }
+
+    /// Parses a string, checking it against the string and input limits.
+    fn parse_str(&mut self) -> Result<Reference<'de, '_, str>> {
+        let start = self.read.position();
+        let trim = self.ignore_whitespace && self.read.peek()? != Some(b'\'');
+        let (max_len, code) = self.str_limit(start);
+        let single_line = mem::take(&mut self.single_line);
+        let s = self
+            .read
+            .parse_str_limited(&mut self.scratch, max_len, single_line)
+            .map_err(|err| str_limit_error(err, code, start))?;
+        Ok(match s {
+            Reference::Borrowed(s) if trim => {
+                Reference::Borrowed(s.trim_end_matches(is_whitespace))
+            }
+            Reference::Copied(s) if trim => Reference::Copied(s.trim_end_matches(is_whitespace)),
+            s => s,
+        })
+    }
+
+    /// Parses a string as bytes, checking it against the string and input
+    /// limits.
+    fn parse_str_raw(&mut self) -> Result<Reference<'de, '_, [u8]>> {
+        let start = self.read.position();
+        let trim = self.ignore_whitespace && self.read.peek()? != Some(b'\'');
+        let (max_len, code) = self.str_limit(start);
+        let single_line = mem::take(&mut self.single_line);
+        let s = self
+            .read
+            .parse_str_raw_limited(&mut self.scratch, max_len, single_line)
+            .map_err(|err| str_limit_error(err, code, start))?;
+        Ok(match s {
+            Reference::Borrowed(s) if trim => Reference::Borrowed(trim_end_whitespace(s)),
+            Reference::Copied(s) if trim => Reference::Copied(trim_end_whitespace(s)),
+            s => s,
+        })
+    }
+
+    #[inline]
+    fn eat_char(&mut self) {
+        self.read.eat_char()
+    }
+
     #[cold]
     fn error(&self, code: ErrorCode) -> Error {
-        let position = self.read.position();
-        Error { code, position }
+        Error::syntax(code, self.read.position())
+    }
+
+    /// Consumes the offending byte before reporting, so that the position
+    /// points just past it like every other error.
+    #[cold]
+    fn peek_error(&mut self, code: ErrorCode) -> Error {
+        self.eat_char();
+        self.error(code)
     }
 
     #[cold]
@@ -104,321 +669,1585 @@ impl<R: read::Read> Deserializer<R> {
         self.fix_position(err)
     }
 
-    #[cold]
-    fn invalid_type(&mut self, b: Option<u8>, exp: &dyn Expected) -> Error {
-        let err = match b.unwrap_or(b'\x00') {
-            b'!' => self.invalid_escaped_type(self.read.next(), exp),
-            b'(' => de::Error::invalid_type(Unexpected::Map, exp),
-            _ => todo!(),
-        };
-        self.fix_position(err)
+    #[cold]
+    fn peek_invalid_type(&mut self, exp: &dyn Expected) -> Error {
+        let peek = match self.peek() {
+            Ok(peek) => peek,
+            Err(err) => return err,
+        };
+        let err = match peek {
+            None => return self.error(ErrorCode::EofWhileParsingValue),
+            Some(b'!') => {
+                self.eat_char();
+                let b = match self.next_char() {
+                    Ok(b) => b,
+                    Err(err) => return err,
+                };
+                return self.invalid_escaped_type(b, exp);
+            }
+            Some(b'(') => {
+                self.eat_char();
+                de::Error::invalid_type(Unexpected::Map, exp)
+            }
+            Some(b'-' | b'0'..=b'9') => match self.parse_any_number() {
+                Ok(n) => n.invalid_type(exp),
+                Err(err) => return err,
+            },
+            Some(b) if b == b'\'' || is_id_start(b) => match self.parse_str() {
+                Ok(s) => de::Error::invalid_type(Unexpected::Str(&s), exp),
+                Err(err) => return err,
+            },
+            Some(_) => return self.peek_error(ErrorCode::ExpectedSomeValue),
+        };
+        self.fix_position(err)
+    }
+
+    /// Attaches the current position to errors that were raised without one,
+    /// such as the ones coming out of a visitor.
+    #[cold]
+    fn fix_position(&self, mut err: Error) -> Error {
+        if err.position.is_none() {
+            err.position = Some(self.read.position());
+        }
+        err
+    }
+
+    fn deserialize_number<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'-' | b'0'..=b'9') => self.parse_any_number()?.visit(visitor),
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    /// Integers are parsed exactly: a literal that does not fit the 64-bit
+    /// range is an error instead of being rounded through `f64`.
+    fn deserialize_integer<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'-' | b'0'..=b'9') => self.parse_integer()?.visit(visitor),
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_integer128<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'-' | b'0'..=b'9') => self.parse_integer128()?.visit(visitor),
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    /// Parses any number, falling back to `f64` for integers that do not fit
+    /// in 64 bits.
+    fn parse_any_number(&mut self) -> Result<ParserNumber> {
+        match self.peek()? {
+            Some(b'-') => {
+                self.eat_char();
+                self.parse_significand(false, false)
+            }
+            _ => self.parse_significand(true, false),
+        }
+    }
+
+    /// `parse_any_number` for `deserialize_any`, which cannot tell whether a
+    /// float is wanted: integers past the u64/i64 range are kept as 128-bit
+    /// integers rather than approximated, and rejected past those.
+    fn parse_any_number_wide(&mut self) -> Result<ParserNumber> {
+        let number = self.parse_any_number()?;
+        // Floats leave their literal in scratch; one without a fraction or
+        // exponent is an integer that overflowed, or `-0`.
+        let integer = matches!(number, ParserNumber::F64(_))
+            && !self.scratch.iter().any(|b| matches!(b, b'.' | b'e'))
+            && self.scratch != b"-0";
+        if !integer {
+            return Ok(number);
+        }
+        let literal = str::from_utf8(&self.scratch).unwrap_or_default();
+        match (literal.parse::<u128>(), literal.parse::<i128>()) {
+            (Ok(x), _) => Ok(ParserNumber::U128(x)),
+            (_, Ok(x)) => Ok(ParserNumber::I128(x)),
+            _ => Err(self.error(ErrorCode::NumberOutOfRange)),
+        }
+    }
+
+    fn parse_integer(&mut self) -> Result<ParserNumber> {
+        match self.peek()? {
+            Some(b'-') => {
+                self.eat_char();
+                self.parse_significand(false, true)
+            }
+            _ => self.parse_significand(true, true),
+        }
+    }
+
+    fn parse_integer128(&mut self) -> Result<ParserNumber> {
+        let positive = match self.peek()? {
+            Some(b'-') => {
+                self.eat_char();
+                false
+            }
+            _ => true,
+        };
+
+        let mut significand: u128 = match self.next_char()? {
+            Some(b'0') => match self.peek_or_null()? {
+                b'0'..=b'9' => return Err(self.peek_error(ErrorCode::InvalidNumber)),
+                _ => 0,
+            },
+            Some(c @ b'1'..=b'9') => (c - b'0') as u128,
+            Some(_) => return Err(self.error(ErrorCode::InvalidNumber)),
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        while let c @ b'0'..=b'9' = self.peek_or_null()? {
+            significand = match significand
+                .checked_mul(10)
+                .and_then(|s| s.checked_add((c - b'0') as u128))
+            {
+                Some(s) => s,
+                None => return Err(self.peek_error(ErrorCode::NumberOutOfRange)),
+            };
+            self.eat_char();
+        }
+
+        if let b'.' | b'e' = self.peek_or_null()? {
+            // Not an integer, but let the visitor report the float.
+            return match u64::try_from(significand) {
+                Ok(significand) => self.parse_number(positive, significand, true),
+                Err(_) => Err(self.peek_error(ErrorCode::NumberOutOfRange)),
+            };
+        }
+
+        Ok(if positive {
+            match u64::try_from(significand) {
+                Ok(x) => ParserNumber::U64(x),
+                Err(_) => ParserNumber::U128(significand),
+            }
+        } else if significand <= i128::MIN.unsigned_abs() {
+            let neg = (significand as i128).wrapping_neg();
+            match i64::try_from(neg) {
+                Ok(x) => ParserNumber::I64(x),
+                Err(_) => ParserNumber::I128(neg),
+            }
+        } else {
+            return Err(self.error(ErrorCode::NumberOutOfRange));
+        })
+    }
+
+    /// With `exact`, integers outside the u64/i64 range are reported as
+    /// `NumberOutOfRange` rather than approximated by a float.
+    fn parse_significand(&mut self, positive: bool, exact: bool) -> Result<ParserNumber> {
+        let next = match self.next_char()? {
+            Some(b) => b,
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        match next {
+            b'0' => match self.peek_or_null()? {
+                b'0'..=b'9' => Err(self.peek_error(ErrorCode::InvalidNumber)),
+                _ => self.parse_number(positive, 0, exact),
+            },
+            c @ b'1'..=b'9' => {
+                let mut significand = (c - b'0') as u64;
+                loop {
+                    match self.peek_or_null()? {
+                        c @ b'0'..=b'9' => {
+                            let digit = (c - b'0') as u64;
+                            if significand >= u64::MAX / 10
+                                && (significand > u64::MAX / 10 || digit > u64::MAX % 10)
+                            {
+                                if exact {
+                                    return Err(self.peek_error(ErrorCode::NumberOutOfRange));
+                                }
+                                // Past u64::MAX the value can only be kept as
+                                // a float, so continue with the remaining
+                                // digits counted as exponent.
+                                return Ok(ParserNumber::F64(
+                                    self.parse_long_integer(positive, significand)?,
+                                ));
+                            }
+                            self.eat_char();
+                            significand = significand * 10 + digit;
+                        }
+                        _ => return self.parse_number(positive, significand, exact),
+                    }
+                }
+            }
+            _ => Err(self.error(ErrorCode::InvalidNumber)),
+        }
+    }
+
+    fn parse_number(
+        &mut self,
+        positive: bool,
+        significand: u64,
+        exact: bool,
+    ) -> Result<ParserNumber> {
+        Ok(match self.peek_or_null()? {
+            b'.' => {
+                self.begin_float(positive, significand);
+                ParserNumber::F64(self.parse_decimal()?)
+            }
+            b'e' => {
+                self.begin_float(positive, significand);
+                ParserNumber::F64(self.parse_exponent()?)
+            }
+            _ => {
+                if positive {
+                    ParserNumber::U64(significand)
+                } else {
+                    let neg = (significand as i64).wrapping_neg();
+                    if neg < 0 || (exact && significand == 0) {
+                        ParserNumber::I64(neg)
+                    } else if exact {
+                        return Err(self.error(ErrorCode::NumberOutOfRange));
+                    } else {
+                        // Convert into a float if we underflow, which keeps
+                        // the sign of `-0` as well.
+                        self.begin_float(positive, significand);
+                        ParserNumber::F64(self.f64_from_scratch()?)
+                    }
+                }
+            }
+        })
+    }
+
+    /// Floats are collected as text in the scratch buffer and converted in a
+    /// single step at the end, so that the result is correctly rounded. Every
+    /// `ParserNumber::F64` leaves its literal there, which `deserialize_f32`
+    /// relies on to convert it again without rounding twice.
+    fn begin_float(&mut self, positive: bool, significand: u64) {
+        self.scratch.clear();
+        if !positive {
+            self.scratch.push(b'-');
+        }
+        let mut buf = itoa::Buffer::new();
+        self.scratch
+            .extend_from_slice(buf.format(significand).as_bytes());
+    }
+
+    fn parse_long_integer(&mut self, positive: bool, significand: u64) -> Result<f64> {
+        self.begin_float(positive, significand);
+        while let c @ b'0'..=b'9' = self.peek_or_null()? {
+            self.eat_char();
+            self.scratch.push(c);
+        }
+
+        match self.peek_or_null()? {
+            b'.' => self.parse_decimal(),
+            b'e' => self.parse_exponent(),
+            _ => self.f64_from_scratch(),
+        }
+    }
+
+    fn parse_decimal(&mut self) -> Result<f64> {
+        self.eat_char();
+        self.scratch.push(b'.');
+
+        let mut at_least_one_digit = false;
+        while let c @ b'0'..=b'9' = self.peek_or_null()? {
+            self.eat_char();
+            self.scratch.push(c);
+            at_least_one_digit = true;
+        }
+
+        if !at_least_one_digit {
+            return match self.peek()? {
+                Some(_) => Err(self.peek_error(ErrorCode::InvalidNumber)),
+                None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+            };
+        }
+
+        match self.peek_or_null()? {
+            b'e' => self.parse_exponent(),
+            _ => self.f64_from_scratch(),
+        }
+    }
+
+    fn parse_exponent(&mut self) -> Result<f64> {
+        self.eat_char();
+        self.scratch.push(b'e');
+
+        match self.peek_or_null()? {
+            b'+' => self.eat_char(),
+            b'-' => {
+                self.eat_char();
+                self.scratch.push(b'-');
+            }
+            _ => {}
+        }
+
+        // Make sure a digit follows the exponent place.
+        match self.next_char()? {
+            Some(c @ b'0'..=b'9') => self.scratch.push(c),
+            Some(_) => return Err(self.error(ErrorCode::InvalidNumber)),
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        }
+
+        while let c @ b'0'..=b'9' = self.peek_or_null()? {
+            self.eat_char();
+            self.scratch.push(c);
+        }
+
+        self.f64_from_scratch()
+    }
+
+    /// The scratch buffer holds a validated literal here, so the conversion
+    /// can only go wrong by overflowing to infinity.
+    fn f64_from_scratch(&self) -> Result<f64> {
+        match str::from_utf8(&self.scratch).map(str::parse::<f64>) {
+            Ok(Ok(f)) if f.is_finite() => Ok(f),
+            _ => Err(self.error(ErrorCode::NumberOutOfRange)),
+        }
+    }
+
+    fn f32_from_scratch(&self) -> Result<f32> {
+        match str::from_utf8(&self.scratch).map(str::parse::<f32>) {
+            Ok(Ok(f)) if f.is_finite() => Ok(f),
+            _ => Err(self.error(ErrorCode::NumberOutOfRange)),
+        }
+    }
+
+    /// Parses a map key as a string, to check it against the keys already
+    /// seen in the map.
+    fn parse_key(&mut self) -> Result<String> {
+        match self.peek()? {
+            Some(b'-' | b'0'..=b'9') => Ok(match self.parse_any_number()? {
+                ParserNumber::U64(x) => x.to_string(),
+                ParserNumber::I64(x) => x.to_string(),
+                ParserNumber::F64(x) => ryu::Buffer::new().format_finite(x).to_string(),
+                ParserNumber::U128(x) => x.to_string(),
+                ParserNumber::I128(x) => x.to_string(),
+            }),
+            Some(b) if b == b'\'' || is_id_start(b) => Ok(self.parse_str()?.to_string()),
+            Some(_) => Err(self.peek_error(ErrorCode::KeyMustBeAString)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        }
+    }
+
+    /// Parses `:` between a key and its value.
+    fn parse_object_colon(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Some(b':') => Ok(()),
+            Some(b) => Err(self.error(ErrorCode::InvalidChar(b, b':'))),
+            None => Err(self.error(ErrorCode::EofWhileParsingObject)),
+        }
+    }
+
+    fn end_seq(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Some(b')') => Ok(()),
+            Some(b',') => match self.peek()? {
+                Some(b')') => Err(self.peek_error(ErrorCode::TrailingComma)),
+                _ => Err(self.error(ErrorCode::TrailingCharacters)),
+            },
+            Some(_) => Err(self.error(ErrorCode::TrailingCharacters)),
+            None => Err(self.error(ErrorCode::EofWhileParsingList)),
+        }
+    }
+
+    fn end_map(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Some(b')') => Ok(()),
+            Some(b',') => Err(self.error(ErrorCode::TrailingCharacters)),
+            Some(_) => Err(self.error(ErrorCode::TrailingCharacters)),
+            None => Err(self.error(ErrorCode::EofWhileParsingObject)),
+        }
+    }
+
+    fn ignore_value(&mut self) -> Result<()> {
+        match self.peek()? {
+            Some(b'!') => {
+                self.eat_char();
+                match self.next_char()? {
+                    Some(b't' | b'f' | b'n') => Ok(()),
+                    Some(b'(') => self.recurse(Self::ignore_seq),
+                    Some(b) => Err(self.error(ErrorCode::InvalidEscape(b))),
+                    None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+                }
+            }
+            Some(b'(') => {
+                self.eat_char();
+                self.recurse(Self::ignore_map)
+            }
+            Some(b'-' | b'0'..=b'9') => self.parse_any_number().map(|_| ()),
+            Some(b) if b == b'\'' || is_id_start(b) => self.parse_str_raw().map(|_| ()),
+            Some(_) => Err(self.peek_error(ErrorCode::ExpectedSomeValue)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        }
+    }
+
+    fn ignore_seq(&mut self) -> Result<()> {
+        if self.peek()? == Some(b')') {
+            self.eat_char();
+            return Ok(());
+        }
+        let mut len = 0;
+        loop {
+            if len == self.max_collection_len {
+                return Err(self.peek_error(ErrorCode::TooManyElements));
+            }
+            len += 1;
+            self.ignore_value()?;
+            match self.next_token()? {
+                Some(b')') => return Ok(()),
+                Some(b',') => {
+                    if self.peek()? == Some(b')') {
+                        return Err(self.peek_error(ErrorCode::TrailingComma));
+                    }
+                }
+                Some(_) => return Err(self.error(ErrorCode::ExpectedListCommaOrEnd)),
+                None => return Err(self.error(ErrorCode::EofWhileParsingList)),
+            }
+        }
+    }
+
+    fn ignore_map(&mut self) -> Result<()> {
+        if self.peek()? == Some(b')') {
+            self.eat_char();
+            return Ok(());
+        }
+        let mut len = 0;
+        loop {
+            if len == self.max_collection_len {
+                return Err(self.peek_error(ErrorCode::TooManyElements));
+            }
+            len += 1;
+            self.ignore_value()?;
+            self.parse_object_colon()?;
+            self.ignore_value()?;
+            match self.next_token()? {
+                Some(b')') => return Ok(()),
+                Some(b',') => {
+                    if self.peek()? == Some(b')') {
+                        return Err(self.peek_error(ErrorCode::TrailingComma));
+                    }
+                }
+                Some(_) => return Err(self.error(ErrorCode::ExpectedObjectCommaOrEnd)),
+                None => return Err(self.error(ErrorCode::EofWhileParsingObject)),
+            }
+        }
+    }
+}
+
+impl<'de, R> de::Deserializer<'de> for &mut Deserializer<R>
+where
+    R: read::Read<'de>,
+{
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let peek = match self.peek()? {
+            Some(b) => b,
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+
+        let value = match peek {
+            b'!' => {
+                self.eat_char();
+                match self.next_char()? {
+                    Some(b't') => visitor.visit_bool(true),
+                    Some(b'f') => visitor.visit_bool(false),
+                    Some(b'n') => visitor.visit_unit(),
+                    Some(b'(') => {
+                        let ret = self.recurse(|de| visitor.visit_seq(SeqAccess::new(de)));
+                        match (ret, self.end_seq()) {
+                            (Ok(ret), Ok(())) => Ok(ret),
+                            (Err(err), _) | (_, Err(err)) => Err(err),
+                        }
+                    }
+                    Some(b) => Err(self.error(ErrorCode::InvalidEscape(b))),
+                    None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+                }
+            }
+            b'(' => {
+                self.eat_char();
+                let ret = self.recurse(|de| visitor.visit_map(MapAccess::new(de)));
+                match (ret, self.end_map()) {
+                    (Ok(ret), Ok(())) => Ok(ret),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                }
+            }
+            b'-' | b'0'..=b'9' => self.parse_any_number_wide()?.visit(visitor),
+            b if b == b'\'' || is_id_start(b) => match self.parse_str()? {
+                Reference::Borrowed(s) => visitor.visit_borrowed_str(s),
+                Reference::Copied(s) => visitor.visit_str(s),
+            },
+            _ => Err(self.peek_error(ErrorCode::ExpectedSomeValue)),
+        };
+
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek()? {
+            Some(b'!') => self.eat_char(),
+            Some(_) => return Err(self.peek_invalid_type(&visitor)),
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        let value = match self.next_char()? {
+            Some(b't') => visitor.visit_bool(true),
+            Some(b'f') => visitor.visit_bool(false),
+            b => Err(self.invalid_escaped_type(b, &visitor)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer128(visitor)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_integer128(visitor)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'-' | b'0'..=b'9') => match self.parse_any_number()? {
+                ParserNumber::F64(_) => visitor.visit_f32(self.f32_from_scratch()?),
+                n => n.visit(visitor),
+            },
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_number(visitor)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b) if b == b'\'' || is_id_start(b) => match self.parse_str()? {
+                Reference::Borrowed(s) => visitor.visit_borrowed_str(s),
+                Reference::Copied(s) => visitor.visit_str(s),
+            },
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    /// Accepts either a string, whose UTF-8 bytes are visited, or the
+    /// `!(...)` sequence of integers that the serializer writes for bytes.
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'!') => return self.deserialize_seq(visitor),
+            Some(b) if b == b'\'' || is_id_start(b) => match self.parse_str_raw()? {
+                Reference::Borrowed(b) => visitor.visit_borrowed_bytes(b),
+                Reference::Copied(b) => visitor.visit_bytes(b),
+            },
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match (self.peek()?, self.read.peek_second()?) {
+            (Some(b'!'), Some(b'n')) => {
+                self.eat_char();
+                self.eat_char();
+                visitor.visit_none()
+            }
+            (Some(_), _) => visitor.visit_some(self),
+            (None, _) => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek()? {
+            Some(b'!') => self.eat_char(),
+            Some(_) => return Err(self.peek_invalid_type(&visitor)),
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        let value = match self.next_char()? {
+            Some(b'n') => visitor.visit_unit(),
+            b => Err(self.invalid_escaped_type(b, &visitor)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek()? {
+            Some(b'!') => self.eat_char(),
+            Some(_) => return Err(self.peek_invalid_type(&visitor)),
+            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        let value = match self.next_char()? {
+            Some(b'(') => {
+                let ret = self.recurse(|de| visitor.visit_seq(SeqAccess::new(de)));
+                match (ret, self.end_seq()) {
+                    (Ok(ret), Ok(())) => Ok(ret),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                }
+            }
+            b => Err(self.invalid_escaped_type(b, &visitor)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'(') => {
+                self.eat_char();
+                let ret = self.recurse(|de| visitor.visit_map(MapAccess::new(de)));
+                match (ret, self.end_map()) {
+                    (Ok(ret), Ok(())) => Ok(ret),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                }
+            }
+            Some(_) => Err(self.peek_invalid_type(&visitor)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match (self.peek()?, self.read.peek_second()?) {
+            (Some(b'!'), Some(b'(')) => self.deserialize_seq(visitor),
+            _ => self.deserialize_map(visitor),
+        }
+    }
+
+    /// Unit variants are plain strings, every other variant is written as a
+    /// single entry object `(variant:value)`.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self.peek()? {
+            Some(b'(') => {
+                self.eat_char();
+                let value = self.recurse(|de| visitor.visit_enum(VariantAccess::new(de)));
+                match (value, self.next_token()?) {
+                    (Err(err), _) => Err(err),
+                    (Ok(value), Some(b')')) => Ok(value),
+                    (Ok(_), Some(b)) => Err(self.error(ErrorCode::InvalidChar(b, b')'))),
+                    (Ok(_), None) => Err(self.error(ErrorCode::EofWhileParsingObject)),
+                }
+            }
+            Some(b) if b == b'\'' || is_id_start(b) => {
+                visitor.visit_enum(UnitVariantAccess::new(self))
+            }
+            Some(_) => Err(self.peek_error(ErrorCode::ExpectedSomeValue)),
+            None => Err(self.error(ErrorCode::EofWhileParsingValue)),
+        };
+        value.map_err(|err| self.fix_position(err))
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.ignore_value()?;
+        visitor.visit_unit().map_err(|err| self.fix_position(err))
+    }
+}
+
+struct SeqAccess<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+    /// Index of the next element.
+    index: usize,
+    /// The sequence is not enclosed in `!(` and `)` and ends with the input,
+    /// for A-Rison.
+    bare: bool,
+}
+
+impl<'a, R: 'a> SeqAccess<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        SeqAccess {
+            de,
+            index: 0,
+            bare: false,
+        }
+    }
+
+    fn bare(de: &'a mut Deserializer<R>) -> Self {
+        SeqAccess {
+            de,
+            index: 0,
+            bare: true,
+        }
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::SeqAccess<'de> for SeqAccess<'a, R> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let peek = match self.de.peek()? {
+            Some(b')') if !self.bare => return Ok(None),
+            None if self.bare => return Ok(None),
+            Some(b',') if self.index != 0 => {
+                self.de.eat_char();
+                self.de.peek()?
+            }
+            Some(b) => {
+                if self.index == 0 {
+                    Some(b)
+                } else if self.bare {
+                    return Err(self.de.peek_error(ErrorCode::TrailingCharacters));
+                } else {
+                    return Err(self.de.peek_error(ErrorCode::ExpectedListCommaOrEnd));
+                }
+            }
+            None => return Err(self.de.error(ErrorCode::EofWhileParsingList)),
+        };
+
+        match peek {
+            Some(b')') if !self.bare => Err(self.de.peek_error(ErrorCode::TrailingComma)),
+            Some(_) if self.index == self.de.max_collection_len => {
+                Err(self.de.peek_error(ErrorCode::TooManyElements))
+            }
+            Some(_) => match seed.deserialize(&mut *self.de) {
+                Ok(value) => {
+                    self.index += 1;
+                    Ok(Some(value))
+                }
+                Err(err) => Err(err.at(Segment::Index(self.index))),
+            },
+            None if self.bare => Err(self.de.error(ErrorCode::TrailingComma)),
+            None => Err(self.de.error(ErrorCode::EofWhileParsingValue)),
+        }
+    }
+}
+
+struct MapAccess<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+    /// Number of entries so far.
+    len: usize,
+    /// The map is not enclosed in parentheses and ends with the input, for
+    /// O-Rison.
+    bare: bool,
+    /// Keys seen so far, unless duplicate keys are left to the visitor.
+    seen: BTreeSet<String>,
+    /// The current key, for the path of errors in its value.
+    key: String,
+}
+
+impl<'a, R: 'a> MapAccess<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        MapAccess {
+            de,
+            len: 0,
+            bare: false,
+            seen: BTreeSet::new(),
+            key: String::new(),
+        }
+    }
+
+    fn bare(de: &'a mut Deserializer<R>) -> Self {
+        MapAccess {
+            de,
+            len: 0,
+            bare: true,
+            seen: BTreeSet::new(),
+            key: String::new(),
+        }
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> MapAccess<'a, R> {
+    /// Moves to the next key, returning false at the end of the map.
+    fn has_next_key(&mut self) -> Result<bool> {
+        let peek = match self.de.peek()? {
+            Some(b')') if !self.bare => return Ok(false),
+            None if self.bare => return Ok(false),
+            Some(b',') if self.len != 0 => {
+                self.de.eat_char();
+                self.de.peek()?
+            }
+            Some(b) => {
+                if self.len == 0 {
+                    Some(b)
+                } else if self.bare {
+                    return Err(self.de.peek_error(ErrorCode::TrailingCharacters));
+                } else {
+                    return Err(self.de.peek_error(ErrorCode::ExpectedObjectCommaOrEnd));
+                }
+            }
+            None => return Err(self.de.error(ErrorCode::EofWhileParsingObject)),
+        };
+
+        match peek {
+            Some(b')') if !self.bare => Err(self.de.peek_error(ErrorCode::TrailingComma)),
+            Some(_) if self.len == self.de.max_collection_len => {
+                Err(self.de.peek_error(ErrorCode::TooManyElements))
+            }
+            Some(_) => {
+                self.len += 1;
+                Ok(true)
+            }
+            None if self.bare => Err(self.de.error(ErrorCode::TrailingComma)),
+            None => Err(self.de.error(ErrorCode::EofWhileParsingValue)),
+        }
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::MapAccess<'de> for MapAccess<'a, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.de.duplicate_keys == DuplicateKeys::LastWins {
+            if !self.has_next_key()? {
+                return Ok(None);
+            }
+            self.key.clear();
+            let key = MapKey {
+                de: &mut *self.de,
+                key: Some(&mut self.key),
+            };
+            return seed.deserialize(key).map(Some);
+        }
+
+        loop {
+            if !self.has_next_key()? {
+                return Ok(None);
+            }
+            let position = self.de.read.position() + 1;
+            let key = self.de.parse_key()?;
+            if !self.seen.contains(&key) {
+                self.seen.insert(key.clone());
+                self.key.clone_from(&key);
+                return match seed.deserialize(MapKeyDeserializer { key }) {
+                    Ok(key) => Ok(Some(key)),
+                    Err(err) => Err(self.de.fix_position(err)),
+                };
+            }
+            match self.de.duplicate_keys {
+                DuplicateKeys::Error => {
+                    let code = ErrorCode::DuplicateKey(key.into_boxed_str());
+                    return Err(Error::syntax(code, position));
+                }
+                DuplicateKeys::FirstWins => {
+                    self.de.parse_object_colon()?;
+                    self.de.ignore_value()?;
+                }
+                DuplicateKeys::LastWins => unreachable!(),
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.de.parse_object_colon()?;
+        seed.deserialize(&mut *self.de)
+            .map_err(|err| err.at(Segment::Key(self.key.as_str().into())))
+    }
+}
+
+struct VariantAccess<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'a, R: 'a> VariantAccess<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        VariantAccess { de }
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::EnumAccess<'de> for VariantAccess<'a, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let key = MapKey {
+            de: &mut *self.de,
+            key: None,
+        };
+        let val = seed.deserialize(key)?;
+        self.de.parse_object_colon()?;
+        Ok((val, self))
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::VariantAccess<'de> for VariantAccess<'a, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_struct(self.de, "", fields, visitor)
+    }
+}
+
+struct UnitVariantAccess<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'a, R: 'a> UnitVariantAccess<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        UnitVariantAccess { de }
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::EnumAccess<'de> for UnitVariantAccess<'a, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::VariantAccess<'de> for UnitVariantAccess<'a, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, _seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        Err(de::Error::invalid_type(
+            Unexpected::UnitVariant,
+            &"newtype variant",
+        ))
     }
 
-    #[cold]
-    fn fix_position(&self, err: Error) -> Error {
-        self.error(err.code)
+    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        Err(de::Error::invalid_type(
+            Unexpected::UnitVariant,
+            &"tuple variant",
+        ))
     }
 
-    fn deserialize_number<'de, V>(&mut self, visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        let peek = match self.read.peak() {
-            Some(b) => b,
-            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
-        };
-        let f = match peek {
-            b'-' => {
-                self.read.eat_char();
-                self.parse_significand(false)?
-            }
-            b'0'..=b'9' => self.parse_significand(true)?,
-            _ => {
-                self.read.eat_char();
-                return Err(self.invalid_type(Some(peek), &visitor));
-            }
-        };
-        visitor.visit_f64(f)
+        Err(de::Error::invalid_type(
+            Unexpected::UnitVariant,
+            &"struct variant",
+        ))
     }
+}
 
-    fn parse_significand(&mut self, positive: bool) -> Result<f64> {
-        let next = match self.read.next() {
-            Some(b) => b,
-            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
+/// Only deserialize from this after peeking a key. Keys are regular values,
+/// except that numbers are also accepted where a string is expected since the
+/// serializer writes integer keys unquoted, e.g. `(1:a)`.
+struct MapKey<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+    /// Where to record the key as a string, for the path of errors.
+    key: Option<&'a mut String>,
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> MapKey<'a, R> {
+    fn deserialize_number_as_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let mut itoa = itoa::Buffer::new();
+        let mut ryu = ryu::Buffer::new();
+        let s = match self.de.parse_any_number()? {
+            ParserNumber::U64(x) => itoa.format(x),
+            ParserNumber::I64(x) => itoa.format(x),
+            ParserNumber::F64(x) => ryu.format_finite(x),
+            ParserNumber::U128(x) => itoa.format(x),
+            ParserNumber::I128(x) => itoa.format(x),
         };
-        match next {
-            b'0' => match self.read.peek_or_null() {
-                b'0'..=b'9' => return Err(self.error(ErrorCode::InvalidNumber)),
-                _ => self.parse_number(positive, 0),
-            },
-            c @ b'1'..=b'9' => {
-                let mut significand = (c - b'0') as f64;
-                loop {
-                    match self.read.peek_or_null() {
-                        c @ b'0'..=b'9' => {
-                            significand = significand * 10.0 + (c - b'0') as f64;
-                        }
-                        _ => self.parse_number(positive, significand),
-                    }
-                }
-            }
+        if let Some(key) = self.key {
+            key.push_str(s);
         }
+        visitor
+            .visit_str(s)
+            .map_err(|err| self.de.fix_position(err))
     }
 
-    fn parse_number(&mut self, positive: bool, significand: f64) -> Result<ParserNumber> {
-        match self.read.peek_or_null() {
-            b'.' => self.parse_decimal(positive, significand, 0),
-            b'e' => self.parse_exponent(positive, significand, 0),
-            _ => Ok(if positive { significand } else { -significand }),
-        }
+    fn deserialize_string_key<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = match self.de.parse_str()? {
+            Reference::Borrowed(s) => {
+                if let Some(key) = self.key {
+                    key.push_str(s);
+                }
+                visitor.visit_borrowed_str(s)
+            }
+            Reference::Copied(s) => {
+                if let Some(key) = self.key {
+                    key.push_str(s);
+                }
+                visitor.visit_str(s)
+            }
+        };
+        value.map_err(|err| self.de.fix_position(err))
     }
+}
 
-    fn parse_decimal(
-        &mut self,
-        positive: bool,
-        mut significand: f64,
-        exponent_before_decimal_point: i32,
-    ) -> Result<f64> {
-        self.read.eat_char();
-
-        let mut exponent_after_decimal_point = 0;
-        while let c @ b'0'..=b'9' = self.read.peek_or_null() {
-            self.eat_char();
-            significand = significand * 10 + (c - b'0') as f64;
-            exponent_after_decimal_point -= 1;
-        }
-
-        if exponent_after_decimal_point == 0 {
-            return match self.read.peak() {
-                Some(_) => Err(self.error(ErrorCode::InvalidNumber)),
-                None => Err(self.error(ErrorCode::EofWhileParsingValue)),
-            };
-        }
-
-        let exponent = exponent_before_decimal_point + exponent_after_decimal_point;
-        match self.read.peek_or_null() {
-            b'e' => self.parse_exponent(positive, significand, exponent),
-            _ => self.f64_from_parts(positive, significand, exponent),
-        }
-    }
+macro_rules! deserialize_delegate_key {
+    ($($method:ident)*) => {
+        $(
+            #[inline]
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de>,
+            {
+                self.de.$method(visitor)
+            }
+        )*
+    };
+}
 
-    fn parse_exponent(
-        &mut self,
-        positive: bool,
-        significand: f64,
-        starting_exp: i32,
-    ) -> Result<f64> {
-        todo!()
-    }
+/// Number keys are parsed here instead of by the deserializer, to record them
+/// for the path of errors.
+macro_rules! deserialize_number_key {
+    ($($method:ident => $parse:ident)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de>,
+            {
+                match self.key {
+                    Some(key) if matches!(self.de.peek()?, Some(b'-' | b'0'..=b'9')) => {
+                        let n = self.de.$parse()?;
+                        n.push_to(key);
+                        n.visit(visitor).map_err(|err| self.de.fix_position(err))
+                    }
+                    _ => self.de.$method(visitor),
+                }
+            }
+        )*
+    };
 }
 
-impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
-where
-    R: read::Read,
-{
+impl<'de, 'a, R: read::Read<'de> + 'a> de::Deserializer<'de> for MapKey<'a, R> {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        match self.de.peek()? {
+            Some(b'-' | b'0'..=b'9') => self.deserialize_number_as_str(visitor),
+            Some(b) if b == b'\'' || is_id_start(b) => self.deserialize_string_key(visitor),
+            _ => self.de.deserialize_any(visitor),
+        }
     }
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        match self.read.next() {
-            Some(b'!') => (),
-            Some(b) => return Err(self.error(ErrorCode::InvalidChar(b, b'!'))),
-            None => return Err(self.error(ErrorCode::EofWhileParsingValue)),
-        };
-        match self.read.next() {
-            Some(b't') => visitor.visit_bool(true),
-            Some(b'f') => visitor.visit_bool(false),
-            b => Err(self.invalid_escaped_type(b, &visitor)),
+        match self.de.peek()? {
+            Some(b'-' | b'0'..=b'9') => self.deserialize_number_as_str(visitor),
+            Some(b) if b == b'\'' || is_id_start(b) => self.deserialize_string_key(visitor),
+            _ => self.de.deserialize_str(visitor),
         }
     }
 
-    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        self.deserialize_number(visitor)
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_i16<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_i32<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.de.deserialize_unit_struct(name, visitor)
     }
 
-    fn deserialize_i64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_i128<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.de.deserialize_tuple(len, visitor)
     }
 
-    fn deserialize_u8<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.de.deserialize_tuple_struct(name, len, visitor)
     }
 
-    fn deserialize_u16<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.de.deserialize_struct(name, fields, visitor)
     }
 
-    fn deserialize_u32<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.de.deserialize_enum(name, variants, visitor)
+    }
+
+    deserialize_number_key! {
+        deserialize_i8 => parse_integer
+        deserialize_i16 => parse_integer
+        deserialize_i32 => parse_integer
+        deserialize_i64 => parse_integer
+        deserialize_i128 => parse_integer128
+        deserialize_u8 => parse_integer
+        deserialize_u16 => parse_integer
+        deserialize_u32 => parse_integer
+        deserialize_u64 => parse_integer
+        deserialize_u128 => parse_integer128
+        deserialize_f64 => parse_any_number
     }
 
-    fn deserialize_u64<V>(self, _visitor: V) -> Result<V::Value>
+    deserialize_delegate_key! {
+        deserialize_bool deserialize_f32 deserialize_char deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_seq deserialize_map
+        deserialize_ignored_any
+    }
+}
+
+/// Deserializes the top-level value as O-Rison, a map without the enclosing
+/// parentheses. Anything but a map or struct is rejected.
+struct OrisonDeserializer<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+macro_rules! deserialize_reject {
+    ($code:ident: $($method:ident)*) => {
+        $(
+            fn $method<V>(self, _visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de>,
+            {
+                Err(self.de.error(ErrorCode::$code))
+            }
+        )*
+    };
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::Deserializer<'de> for OrisonDeserializer<'a, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_map(visitor)
     }
 
-    fn deserialize_u128<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
+    {
+        let de = self.de;
+        let value = match visitor.visit_map(MapAccess::bare(&mut *de)) {
+            Ok(value) => match de.peek()? {
+                Some(_) => Err(de.peek_error(ErrorCode::TrailingCharacters)),
+                None => Ok(value),
+            },
+            Err(err) => Err(err),
+        };
+        value.map_err(|err| de.fix_position(err))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_map(visitor)
     }
 
-    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_map(visitor)
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::OrisonMustBeAMap))
     }
 
-    fn deserialize_str<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::OrisonMustBeAMap))
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::OrisonMustBeAMap))
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::OrisonMustBeAMap))
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    deserialize_reject! {
+        OrisonMustBeAMap:
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_option
+        deserialize_unit deserialize_seq deserialize_identifier
+    }
+}
+
+/// Deserializes the top-level value as A-Rison, a sequence without the
+/// enclosing `!(` and `)`. Anything but a sequence is rejected.
+struct ArisonDeserializer<'a, R: 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'de, 'a, R: read::Read<'de> + 'a> de::Deserializer<'de> for ArisonDeserializer<'a, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        let de = self.de;
+        let value = match visitor.visit_seq(SeqAccess::bare(&mut *de)) {
+            Ok(value) => match de.peek()? {
+                Some(_) => Err(de.peek_error(ErrorCode::TrailingCharacters)),
+                None => Ok(value),
+            },
+            Err(err) => Err(err),
+        };
+        value.map_err(|err| de.fix_position(err))
     }
 
-    fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_unit_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_tuple_struct<V>(
-        self,
-        _name: &'static str,
-        _len: usize,
-        _visitor: V,
-    ) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_seq(visitor)
     }
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::ArisonMustBeASeq))
     }
 
     fn deserialize_struct<V>(
@@ -428,9 +2257,9 @@ where
         _visitor: V,
     ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::ArisonMustBeASeq))
     }
 
     fn deserialize_enum<V>(
@@ -440,32 +2269,111 @@ where
         _visitor: V,
     ) -> Result<V::Value>
     where
-        V: Visitor<'de>,
+        V: de::Visitor<'de>,
     {
-        todo!()
+        Err(self.de.error(ErrorCode::ArisonMustBeASeq))
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        todo!()
+    deserialize_reject! {
+        ArisonMustBeASeq:
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_option deserialize_unit deserialize_map
+        deserialize_identifier
     }
+}
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        todo!()
+/// An iterator over rison values separated by line breaks, created with
+/// `Deserializer::into_iter`.
+///
+/// Blank lines and `\r\n` line endings are accepted. A top-level id ends at
+/// the line break, but a line break in a nested id is taken as part of it, so
+/// such strings have to be quoted, as `ser::to_writer_lines` does.
+pub struct StreamDeserializer<'de, R, T> {
+    de: Deserializer<R>,
+    offset: usize,
+    failed: bool,
+    output: PhantomData<T>,
+    lifetime: PhantomData<&'de ()>,
+}
+
+impl<'de, R, T> StreamDeserializer<'de, R, T>
+where
+    R: read::Read<'de>,
+    T: Deserialize<'de>,
+{
+    /// The number of bytes of input consumed by the values deserialized so
+    /// far, to resume from after an error or to report progress.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    fn next_value(&mut self) -> Result<Option<T>> {
+        while let Some(b'\n' | b'\r') = self.de.peek()? {
+            self.de.eat_char();
+        }
+        if self.de.peek()?.is_none() {
+            self.offset = self.de.read.position();
+            return Ok(None);
+        }
+        // A top-level id ends at the line break, like any other value.
+        self.de.single_line = self.de.peek()?.is_some_and(is_id_start);
+        let value = T::deserialize(&mut self.de);
+        self.de.single_line = false;
+        let value = value.map_err(|err| self.de.fix_position(err))?;
+        if self.de.ignore_whitespace {
+            while let Some(b' ' | b'\t') = self.de.peek_raw()? {
+                self.de.eat_char();
+            }
+        }
+        match self.de.peek_raw()? {
+            Some(b'\n' | b'\r') | None => {
+                self.offset = self.de.read.position();
+                Ok(Some(value))
+            }
+            Some(_) => Err(self.de.peek_error(ErrorCode::TrailingCharacters)),
+        }
     }
 }
 
+impl<'de, R, T> Iterator for StreamDeserializer<'de, R, T>
+where
+    R: read::Read<'de>,
+    T: Deserialize<'de>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_value().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+fn from_trait<'de, R, V>(read: R) -> Result<V>
+where
+    R: read::Read<'de>,
+    V: Deserialize<'de>,
+{
+    let mut de = Deserializer::new(read);
+    let value = V::deserialize(&mut de).map_err(|err| de.fix_position(err))?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Deserializes an instance of `V` from bytes of rison.
+///
+/// Any input, including invalid UTF-8 or truncated escapes, gives an error
+/// rather than a panic; `tests/fuzz.rs` checks this on random input.
 pub fn from_slice<'de, V>(s: &'de [u8]) -> Result<V>
 where
     V: Deserialize<'de>,
 {
-    let mut de = Deserializer::from_slice(s);
-    V::deserialize(&mut de)
+    from_trait(read::SliceRead::new(s))
 }
 
 pub fn from_str<'de, V>(s: &'de str) -> Result<V>
@@ -474,3 +2382,71 @@ where
 {
     from_slice(s.as_bytes())
 }
+
+/// Deserializes an instance of type `V` from O-Rison, the form of rison for
+/// a top-level object that leaves out the enclosing parentheses:
+/// `a:1,b:x`.
+///
+/// An empty string is an empty map. Types that are not deserialized from a
+/// map, such as sequences and primitives, are rejected.
+pub fn from_str_orison<'de, V>(s: &'de str) -> Result<V>
+where
+    V: Deserialize<'de>,
+{
+    let mut de = Deserializer::from_str(s);
+    let value =
+        V::deserialize(OrisonDeserializer { de: &mut de }).map_err(|err| de.fix_position(err))?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Deserializes an instance of type `V` from A-Rison, the form of rison for
+/// a top-level array that leaves out the enclosing `!(` and `)`: `a,b,c`.
+///
+/// An empty string is an empty array. Types that are not deserialized from
+/// a sequence are rejected.
+pub fn from_str_arison<'de, V>(s: &'de str) -> Result<V>
+where
+    V: Deserialize<'de>,
+{
+    let mut de = Deserializer::from_str(s);
+    let value =
+        V::deserialize(ArisonDeserializer { de: &mut de }).map_err(|err| de.fix_position(err))?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Deserializes an instance of type `V` from percent-encoded rison, such as
+/// a query parameter taken straight from a URL. `+` is decoded as a space.
+///
+/// Positions in errors are offsets in the encoded string.
+pub fn from_url_encoded_str<'de, V>(s: &'de str) -> Result<V>
+where
+    V: Deserialize<'de>,
+{
+    from_trait(read::PercentDecodeRead::new(s.as_bytes()).plus_as_space(true))
+}
+
+/// Deserializes an instance of type `V` from an `io::Read`.
+///
+/// The reader is read one byte at a time, so wrapping it in a
+/// `std::io::BufReader` is recommended for files and sockets.
+///
+/// Line breaks after the value are accepted, as files usually end with one.
+/// A top-level id ends at the first line break, as in a stream of rison
+/// lines.
+#[cfg(feature = "std")]
+pub fn from_reader<R, V>(rdr: R) -> Result<V>
+where
+    R: io::Read,
+    V: de::DeserializeOwned,
+{
+    let mut de = Deserializer::from_reader(rdr);
+    de.single_line = de.peek()?.is_some_and(is_id_start);
+    let value = V::deserialize(&mut de).map_err(|err| de.fix_position(err))?;
+    while let Some(b'\n' | b'\r') = de.peek()? {
+        de.eat_char();
+    }
+    de.end()?;
+    Ok(value)
+}
@@ -2,6 +2,43 @@
 
 extern crate alloc;
 
+#[macro_use]
+mod macros;
+
 pub mod de;
-mod read;
+pub mod map;
+mod number;
+mod path;
+pub mod read;
 pub mod ser;
+pub mod value;
+
+pub use crate::ser::display;
+pub use crate::value::{from_value, to_value, Map, Number, Value};
+
+/// What to do with a key that appears more than once in the same map, when
+/// serializing or deserializing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Fail with an error naming the key.
+    Error,
+    /// Keep the first value and drop the later ones.
+    FirstWins,
+    /// Keep the last value, like inserting into a map does. This is the
+    /// default.
+    #[default]
+    LastWins,
+}
+
+/// Runs `f`, first switching to a fresh stack segment if the current one is
+/// close to running out.
+#[cfg(feature = "unbounded_depth")]
+fn maybe_grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(64 * 1024, 2 * 1024 * 1024, f)
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::ser::check_no_inline_args;
+    pub use alloc::{format, vec};
+}
@@ -0,0 +1,362 @@
+//! A map of `String` to `Value`, the representation of a rison object.
+
+use crate::value::Value;
+use alloc::{collections::btree_map, string::String};
+use core::{borrow::Borrow, fmt, iter::FromIterator, ops};
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Represents a rison object. Keys are kept in sorted order.
+pub struct Map<K, V> {
+    map: btree_map::BTreeMap<K, V>,
+}
+
+impl Map<String, Value> {
+    /// Makes a new empty map.
+    #[inline]
+    pub fn new() -> Self {
+        Map {
+            map: btree_map::BTreeMap::new(),
+        }
+    }
+
+    /// Clears the map, removing all values.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    #[inline]
+    pub fn get<Q>(&self, key: &Q) -> Option<&Value>
+    where
+        String: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        String: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    #[inline]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Value>
+    where
+        String: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// Inserts a key-value pair into the map, returning the previous value
+    /// of the key if there was one.
+    #[inline]
+    pub fn insert(&mut self, k: String, v: Value) -> Option<Value> {
+        self.map.insert(k, v)
+    }
+
+    /// Removes a key from the map, returning its value if the key was
+    /// present.
+    #[inline]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Value>
+    where
+        String: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.remove(key)
+    }
+
+    /// Gets the given key's entry in the map for in-place manipulation.
+    #[inline]
+    pub fn entry<S>(&mut self, key: S) -> btree_map::Entry<'_, String, Value>
+    where
+        S: Into<String>,
+    {
+        self.map.entry(key.into())
+    }
+
+    /// Returns the number of elements in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Gets an iterator over the entries of the map.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            iter: self.map.iter(),
+        }
+    }
+
+    /// Gets a mutable iterator over the entries of the map.
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        IterMut {
+            iter: self.map.iter_mut(),
+        }
+    }
+
+    /// Gets an iterator over the keys of the map.
+    #[inline]
+    pub fn keys(&self) -> Keys<'_> {
+        Keys {
+            iter: self.map.keys(),
+        }
+    }
+
+    /// Gets an iterator over the values of the map.
+    #[inline]
+    pub fn values(&self) -> Values<'_> {
+        Values {
+            iter: self.map.values(),
+        }
+    }
+
+    /// Gets a mutable iterator over the values of the map.
+    #[inline]
+    pub fn values_mut(&mut self) -> ValuesMut<'_> {
+        ValuesMut {
+            iter: self.map.values_mut(),
+        }
+    }
+}
+
+impl Default for Map<String, Value> {
+    #[inline]
+    fn default() -> Self {
+        Map::new()
+    }
+}
+
+impl Clone for Map<String, Value> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Map {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl PartialEq for Map<String, Value> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.map.eq(&other.map)
+    }
+}
+
+impl Eq for Map<String, Value> {}
+
+impl fmt::Debug for Map<String, Value> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.map.fmt(formatter)
+    }
+}
+
+/// Access an element of this map. Panics if the given key is not present in
+/// the map.
+impl<Q> ops::Index<&Q> for Map<String, Value>
+where
+    String: Borrow<Q>,
+    Q: ?Sized + Ord,
+{
+    type Output = Value;
+
+    fn index(&self, index: &Q) -> &Value {
+        self.map.index(index)
+    }
+}
+
+/// Mutably access an element of this map. Panics if the given key is not
+/// present in the map.
+impl<Q> ops::IndexMut<&Q> for Map<String, Value>
+where
+    String: Borrow<Q>,
+    Q: ?Sized + Ord,
+{
+    fn index_mut(&mut self, index: &Q) -> &mut Value {
+        self.map.get_mut(index).expect("no entry found for key")
+    }
+}
+
+impl Serialize for Map<String, Value> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Map<String, Value> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor;
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = Map<String, Value>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut values = Map::new();
+                while let Some((key, value)) = visitor.next_entry()? {
+                    values.insert(key, value);
+                }
+                Ok(values)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor)
+    }
+}
+
+impl FromIterator<(String, Value)> for Map<String, Value> {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = (String, Value)>,
+    {
+        Map {
+            map: FromIterator::from_iter(iter),
+        }
+    }
+}
+
+impl Extend<(String, Value)> for Map<String, Value> {
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = (String, Value)>,
+    {
+        self.map.extend(iter);
+    }
+}
+
+macro_rules! delegate_iterator {
+    (($name:ident $($generics:tt)*) => $item:ty) => {
+        impl $($generics)* Iterator for $name $($generics)* {
+            type Item = $item;
+            #[inline]
+            fn next(&mut self) -> Option<Self::Item> {
+                self.iter.next()
+            }
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.iter.size_hint()
+            }
+        }
+
+        impl $($generics)* DoubleEndedIterator for $name $($generics)* {
+            #[inline]
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.iter.next_back()
+            }
+        }
+
+        impl $($generics)* ExactSizeIterator for $name $($generics)* {
+            #[inline]
+            fn len(&self) -> usize {
+                self.iter.len()
+            }
+        }
+    };
+}
+
+impl<'a> IntoIterator for &'a Map<String, Value> {
+    type Item = (&'a String, &'a Value);
+    type IntoIter = Iter<'a>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            iter: self.map.iter(),
+        }
+    }
+}
+
+/// An iterator over a `Map`'s entries.
+pub struct Iter<'a> {
+    iter: btree_map::Iter<'a, String, Value>,
+}
+
+delegate_iterator!((Iter<'a>) => (&'a String, &'a Value));
+
+impl<'a> IntoIterator for &'a mut Map<String, Value> {
+    type Item = (&'a String, &'a mut Value);
+    type IntoIter = IterMut<'a>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        IterMut {
+            iter: self.map.iter_mut(),
+        }
+    }
+}
+
+/// A mutable iterator over a `Map`'s entries.
+pub struct IterMut<'a> {
+    iter: btree_map::IterMut<'a, String, Value>,
+}
+
+delegate_iterator!((IterMut<'a>) => (&'a String, &'a mut Value));
+
+impl IntoIterator for Map<String, Value> {
+    type Item = (String, Value);
+    type IntoIter = IntoIter;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            iter: self.map.into_iter(),
+        }
+    }
+}
+
+/// An owning iterator over a `Map`'s entries.
+pub struct IntoIter {
+    iter: btree_map::IntoIter<String, Value>,
+}
+
+delegate_iterator!((IntoIter) => (String, Value));
+
+/// An iterator over a `Map`'s keys.
+pub struct Keys<'a> {
+    iter: btree_map::Keys<'a, String, Value>,
+}
+
+delegate_iterator!((Keys<'a>) => &'a String);
+
+/// An iterator over a `Map`'s values.
+pub struct Values<'a> {
+    iter: btree_map::Values<'a, String, Value>,
+}
+
+delegate_iterator!((Values<'a>) => &'a Value);
+
+/// A mutable iterator over a `Map`'s values.
+pub struct ValuesMut<'a> {
+    iter: btree_map::ValuesMut<'a, String, Value>,
+}
+
+delegate_iterator!((ValuesMut<'a>) => &'a mut Value);
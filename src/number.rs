@@ -0,0 +1,220 @@
+use core::fmt;
+use serde::de::{self, Deserialize, Deserializer, Unexpected, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// A rison number, which keeps track of whether it was an unsigned, a
+/// negative or a floating point number.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Number {
+    n: N,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum N {
+    PosInt(u64),
+    /// Always less than zero.
+    NegInt(i64),
+    /// Always finite.
+    Float(f64),
+}
+
+// Floats are always finite, so NaN never breaks reflexivity.
+impl Eq for N {}
+
+impl Number {
+    /// Returns true if the number can be represented by an `i64`.
+    pub fn is_i64(&self) -> bool {
+        match self.n {
+            N::PosInt(v) => v <= i64::MAX as u64,
+            N::NegInt(_) => true,
+            N::Float(_) => false,
+        }
+    }
+
+    /// Returns true if the number can be represented by a `u64`.
+    pub fn is_u64(&self) -> bool {
+        matches!(self.n, N::PosInt(_))
+    }
+
+    /// Returns true if the number was written with a fraction or an
+    /// exponent, or does not fit either integer type.
+    pub fn is_f64(&self) -> bool {
+        matches!(self.n, N::Float(_))
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.n {
+            N::PosInt(n) => i64::try_from(n).ok(),
+            N::NegInt(n) => Some(n),
+            N::Float(_) => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.n {
+            N::PosInt(n) => Some(n),
+            N::NegInt(_) | N::Float(_) => None,
+        }
+    }
+
+    /// Converts the number to an `f64`, which may lose precision for large
+    /// integers.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.n {
+            N::PosInt(n) => Some(n as f64),
+            N::NegInt(n) => Some(n as f64),
+            N::Float(n) => Some(n),
+        }
+    }
+
+    /// Converts a finite `f64` to a `Number`. Infinite and NaN values have
+    /// no rison representation and return `None`.
+    pub fn from_f64(f: f64) -> Option<Number> {
+        if f.is_finite() {
+            Some(Number { n: N::Float(f) })
+        } else {
+            None
+        }
+    }
+}
+
+impl Number {
+    pub(crate) fn visit<'de, V, E>(&self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+        E: de::Error,
+    {
+        match self.n {
+            N::PosInt(u) => visitor.visit_u64(u),
+            N::NegInt(i) => visitor.visit_i64(i),
+            N::Float(f) => visitor.visit_f64(f),
+        }
+    }
+
+    pub(crate) fn unexpected(&self) -> Unexpected<'_> {
+        match self.n {
+            N::PosInt(u) => Unexpected::Unsigned(u),
+            N::NegInt(i) => Unexpected::Signed(i),
+            N::Float(f) => Unexpected::Float(f),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.n {
+            N::PosInt(u) => f.write_str(itoa::Buffer::new().format(u)),
+            N::NegInt(i) => f.write_str(itoa::Buffer::new().format(i)),
+            N::Float(v) => f.write_str(ryu::Buffer::new().format_finite(v)),
+        }
+    }
+}
+
+impl fmt::Debug for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Number({})", self)
+    }
+}
+
+impl Serialize for Number {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.n {
+            N::PosInt(u) => serializer.serialize_u64(u),
+            N::NegInt(i) => serializer.serialize_i64(i),
+            N::Float(f) => serializer.serialize_f64(f),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D>(deserializer: D) -> Result<Number, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NumberVisitor;
+
+        impl<'de> Visitor<'de> for NumberVisitor {
+            type Value = Number;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a rison number")
+            }
+
+            fn visit_i64<E>(self, i: i64) -> Result<Number, E> {
+                Ok(i.into())
+            }
+
+            fn visit_u64<E>(self, u: u64) -> Result<Number, E> {
+                Ok(u.into())
+            }
+
+            fn visit_i128<E>(self, i: i128) -> Result<Number, E>
+            where
+                E: de::Error,
+            {
+                if let Ok(u) = u64::try_from(i) {
+                    Ok(u.into())
+                } else if let Ok(i) = i64::try_from(i) {
+                    Ok(i.into())
+                } else {
+                    Err(de::Error::custom("number out of range"))
+                }
+            }
+
+            fn visit_u128<E>(self, u: u128) -> Result<Number, E>
+            where
+                E: de::Error,
+            {
+                match u64::try_from(u) {
+                    Ok(u) => Ok(u.into()),
+                    Err(_) => Err(de::Error::custom("number out of range")),
+                }
+            }
+
+            fn visit_f64<E>(self, f: f64) -> Result<Number, E>
+            where
+                E: de::Error,
+            {
+                Number::from_f64(f)
+                    .ok_or_else(|| de::Error::invalid_value(Unexpected::Float(f), &self))
+            }
+        }
+
+        deserializer.deserialize_any(NumberVisitor)
+    }
+}
+
+macro_rules! impl_from_unsigned {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Number {
+                fn from(u: $ty) -> Self {
+                    Number { n: N::PosInt(u as u64) }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_from_signed {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Number {
+                fn from(i: $ty) -> Self {
+                    let n = if i < 0 {
+                        N::NegInt(i as i64)
+                    } else {
+                        N::PosInt(i as u64)
+                    };
+                    Number { n }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_unsigned!(u8 u16 u32 u64 usize);
+impl_from_signed!(i8 i16 i32 i64 isize);
@@ -0,0 +1,54 @@
+//! Paths to the value an error happened in, like `panels[3].gridData.w`.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::{self, Write};
+
+pub(crate) enum Segment {
+    Index(usize),
+    Key(Box<str>),
+}
+
+/// A path built while an error propagates out of nested values, so the
+/// innermost segment comes first.
+#[derive(Default)]
+pub(crate) struct Path {
+    segments: Vec<Segment>,
+}
+
+impl Path {
+    pub(crate) fn push(&mut self, segment: Segment) {
+        self.segments.push(segment);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.segments.iter().rev().enumerate() {
+            match segment {
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::Key(key) => {
+                    if i != 0 {
+                        f.write_char('.')?;
+                    }
+                    // Keys that are not ids are quoted like in rison, except
+                    // for integers, which are written bare as map keys.
+                    if is_integer(key) {
+                        f.write_str(key)?;
+                    } else {
+                        crate::ser::escaped_str(f, key)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_integer(key: &str) -> bool {
+    let digits = key.strip_prefix('-').unwrap_or(key);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
@@ -1,45 +1,552 @@
-pub trait Read {
-    fn next(&mut self) -> Option<u8>;
+use crate::de::{Error, ErrorCode, Result};
+use crate::ser::NOT_ID;
+use alloc::vec::Vec;
+use core::{ops::Deref, str};
+#[cfg(feature = "std")]
+use std::io;
 
-    fn peak(&mut self) -> Option<u8>;
+/// Trait used by the deserializer for iterating over input.
+///
+/// Besides the sources provided by this module, it can be implemented to
+/// feed the deserializer from anywhere else. Only the byte level methods are
+/// required; the string methods have default implementations that copy each
+/// string into `scratch` one byte at a time.
+pub trait Read<'de> {
+    /// Consumes and returns the next byte, or `None` at the end of input.
+    fn next(&mut self) -> Result<Option<u8>>;
 
-    fn peek_or_null(&mut self) -> u8 {
-        self.peak().unwrap_or(b'\x00')
-    }
+    /// Returns the next byte without consuming it.
+    fn peek(&mut self) -> Result<Option<u8>>;
+
+    /// Looks one byte past `peek`, used to tell `!n` apart from the other
+    /// `!` escapes without consuming anything.
+    fn peek_second(&mut self) -> Result<Option<u8>>;
 
+    /// Number of bytes consumed so far, reported as the position of errors.
     fn position(&self) -> usize;
 
+    /// Consumes the byte returned by the last `peek`. Only called after
+    /// `peek` returned `Some`.
     fn eat_char(&mut self);
+
+    /// Parses the quoted string or id starting at the current position.
+    /// Strings without `!` escapes may be borrowed from the input, the
+    /// others are unescaped into `scratch`.
+    fn parse_str<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's, str>> {
+        parse_str_bytes(self, scratch, usize::MAX, false)?;
+        as_str(self, scratch).map(Reference::Copied)
+    }
+
+    /// Like `parse_str`, but does not check that the string is valid UTF-8.
+    fn parse_str_raw<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        parse_str_bytes(self, scratch, usize::MAX, false)?;
+        Ok(Reference::Copied(scratch))
+    }
+
+    /// `parse_str` as the deserializer calls it: fails as soon as the string
+    /// is longer than `max_len` bytes and, with `single_line`, ends an id at
+    /// a line break, as the top-level values of a stream of rison lines do.
+    ///
+    /// Not public API. The default calls `parse_str` and checks the length
+    /// afterwards, so that sources overriding `parse_str` keep their
+    /// behavior; the sources of this module stop reading early instead.
+    #[doc(hidden)]
+    fn parse_str_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, str>> {
+        if single_line {
+            parse_str_bytes(self, scratch, max_len, true)?;
+            return as_str(self, scratch).map(Reference::Copied);
+        }
+        let position = self.position();
+        check_len(self.parse_str(scratch)?, max_len, position)
+    }
+
+    /// `parse_str_raw` as the deserializer calls it, like
+    /// `parse_str_limited`.
+    #[doc(hidden)]
+    fn parse_str_raw_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        if single_line {
+            parse_str_bytes(self, scratch, max_len, true)?;
+            return Ok(Reference::Copied(scratch));
+        }
+        let position = self.position();
+        check_len(self.parse_str_raw(scratch)?, max_len, position)
+    }
+}
+
+/// Fails with "string too long" if a string parsed without a limit turns out
+/// to be longer than `max_len`.
+fn check_len<'de, 's, T>(
+    s: Reference<'de, 's, T>,
+    max_len: usize,
+    position: usize,
+) -> Result<Reference<'de, 's, T>>
+where
+    T: ?Sized + AsRef<[u8]>,
+{
+    if (*s).as_ref().len() > max_len {
+        return Err(Error::syntax(ErrorCode::StringTooLong, position));
+    }
+    Ok(s)
+}
+
+/// A string that is either borrowed from the input or copied into the
+/// scratch buffer.
+pub enum Reference<'b, 'c, T>
+where
+    T: ?Sized + 'static,
+{
+    Borrowed(&'b T),
+    Copied(&'c T),
+}
+
+impl<'b, 'c, T> Deref for Reference<'b, 'c, T>
+where
+    T: ?Sized + 'static,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match *self {
+            Reference::Borrowed(b) => b,
+            Reference::Copied(c) => c,
+        }
+    }
 }
 
+/// Rison input source that reads from a slice of bytes.
 pub struct SliceRead<'a> {
     slice: &'a [u8],
     index: usize,
 }
 
 impl<'a> SliceRead<'a> {
+    /// Creates a rison input source to read from a slice of bytes.
     pub fn new(slice: &'a [u8]) -> Self {
         SliceRead { slice, index: 0 }
     }
+
+    /// The whole input is in memory already, so the length of the string is
+    /// only checked once it has been found.
+    fn parse_str_bytes<'s, T, F>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+        result: F,
+    ) -> Result<Reference<'a, 's, T>>
+    where
+        T: ?Sized + 's,
+        F: for<'f> FnOnce(&'s Self, &'f [u8]) -> Result<&'f T>,
+    {
+        self.parse_str_unchecked(scratch, single_line, move |read, bytes| {
+            if bytes.len() > max_len {
+                return Err(error(read, ErrorCode::StringTooLong));
+            }
+            result(read, bytes)
+        })
+    }
+
+    fn parse_str_unchecked<'s, T, F>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        single_line: bool,
+        result: F,
+    ) -> Result<Reference<'a, 's, T>>
+    where
+        T: ?Sized + 's,
+        F: for<'f> FnOnce(&'s Self, &'f [u8]) -> Result<&'f T>,
+    {
+        let slice = self.slice;
+
+        if slice.get(self.index) != Some(&b'\'') {
+            // Ids cannot contain escapes, so they are always borrowed.
+            let start = self.index;
+            while self.index < slice.len() && !ends_id(slice[self.index], single_line) {
+                self.index += 1;
+            }
+            return result(self, &slice[start..self.index]).map(Reference::Borrowed);
+        }
+
+        self.index += 1;
+        let mut start = self.index;
+        scratch.clear();
+        loop {
+            while self.index < slice.len() && !matches!(slice[self.index], b'\'' | b'!') {
+                self.index += 1;
+            }
+            if self.index == slice.len() {
+                return Err(error(self, ErrorCode::EofWhileParsingString));
+            }
+            if slice[self.index] == b'\'' {
+                if scratch.is_empty() {
+                    // Fast path: return a slice of the raw input without
+                    // any copying.
+                    let borrowed = &slice[start..self.index];
+                    self.index += 1;
+                    return result(self, borrowed).map(Reference::Borrowed);
+                } else {
+                    scratch.extend_from_slice(&slice[start..self.index]);
+                    self.index += 1;
+                    return result(self, scratch).map(Reference::Copied);
+                }
+            }
+
+            scratch.extend_from_slice(&slice[start..self.index]);
+            self.index += 1;
+            match slice.get(self.index) {
+                Some(&b @ (b'!' | b'\'')) => {
+                    scratch.push(b);
+                    self.index += 1;
+                }
+                Some(&b) => {
+                    self.index += 1;
+                    return Err(error(self, ErrorCode::InvalidEscape(b)));
+                }
+                None => return Err(error(self, ErrorCode::EofWhileParsingString)),
+            }
+            start = self.index;
+        }
+    }
 }
 
-impl<'a> Read for SliceRead<'a> {
-    fn next(&mut self) -> Option<u8> {
-        if self.index < self.slice.len() {
+impl<'a> Read<'a> for SliceRead<'a> {
+    fn next(&mut self) -> Result<Option<u8>> {
+        Ok(if self.index < self.slice.len() {
             let ch = self.slice[self.index];
             self.index += 1;
             Some(ch)
         } else {
             None
+        })
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.slice.get(self.index).copied())
+    }
+
+    fn peek_second(&mut self) -> Result<Option<u8>> {
+        Ok(self.slice.get(self.index + 1).copied())
+    }
+
+    fn position(&self) -> usize {
+        self.index
+    }
+
+    fn eat_char(&mut self) {
+        self.index += 1;
+    }
+
+    fn parse_str<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'a, 's, str>> {
+        self.parse_str_bytes(scratch, usize::MAX, false, as_str)
+    }
+
+    fn parse_str_raw<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'a, 's, [u8]>> {
+        self.parse_str_bytes(scratch, usize::MAX, false, |_, bytes| Ok(bytes))
+    }
+
+    fn parse_str_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'a, 's, str>> {
+        self.parse_str_bytes(scratch, max_len, single_line, as_str)
+    }
+
+    fn parse_str_raw_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'a, 's, [u8]>> {
+        self.parse_str_bytes(scratch, max_len, single_line, |_, bytes| Ok(bytes))
+    }
+}
+
+/// Rison input source that percent-decodes a slice of bytes while reading,
+/// for rison taken straight from a URL.
+///
+/// Positions reported in errors are offsets in the encoded input.
+pub struct PercentDecodeRead<'a> {
+    slice: &'a [u8],
+    index: usize,
+    plus_as_space: bool,
+}
+
+impl<'a> PercentDecodeRead<'a> {
+    /// Creates a rison input source to read from a percent-encoded slice of
+    /// bytes.
+    pub fn new(slice: &'a [u8]) -> Self {
+        PercentDecodeRead {
+            slice,
+            index: 0,
+            plus_as_space: false,
         }
     }
 
-    fn peak(&mut self) -> Option<u8> {
-        if self.index < self.slice.len() {
-            Some(self.slice[self.index])
-        } else {
-            None
+    /// Whether `+` is decoded as a space, as in query strings. Off by
+    /// default.
+    pub fn plus_as_space(mut self, enabled: bool) -> Self {
+        self.plus_as_space = enabled;
+        self
+    }
+
+    /// Whether the raw byte has to be decoded instead of being taken as is.
+    fn is_encoded(&self, b: u8) -> bool {
+        b == b'%' || (b == b'+' && self.plus_as_space)
+    }
+
+    /// Decodes the byte starting at `index` of the encoded input, returning
+    /// it with the number of encoded bytes it spans.
+    fn decode_at(&self, index: usize) -> Result<Option<(u8, usize)>> {
+        Ok(match self.slice.get(index) {
+            None => None,
+            Some(b'%') => {
+                let hi = self.slice.get(index + 1).and_then(|&b| decode_hex(b));
+                let lo = self.slice.get(index + 2).and_then(|&b| decode_hex(b));
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => Some((hi << 4 | lo, 3)),
+                    _ => return Err(Error::syntax(ErrorCode::InvalidPercentEncoding, index + 1)),
+                }
+            }
+            Some(b'+') if self.plus_as_space => Some((b' ', 1)),
+            Some(&b) => Some((b, 1)),
+        })
+    }
+
+    /// Borrows the string from the input when none of it needs decoding or
+    /// unescaping, and falls back to copying it into `scratch` otherwise.
+    fn parse_str_bytes<'s, T, F>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+        result: F,
+    ) -> Result<Reference<'a, 's, T>>
+    where
+        T: ?Sized + 's,
+        F: for<'f> FnOnce(&'s Self, &'f [u8]) -> Result<&'f T>,
+    {
+        self.parse_str_unchecked(scratch, max_len, single_line, move |read, bytes| {
+            if bytes.len() > max_len {
+                return Err(error(read, ErrorCode::StringTooLong));
+            }
+            result(read, bytes)
+        })
+    }
+
+    fn parse_str_unchecked<'s, T, F>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+        result: F,
+    ) -> Result<Reference<'a, 's, T>>
+    where
+        T: ?Sized + 's,
+        F: for<'f> FnOnce(&'s Self, &'f [u8]) -> Result<&'f T>,
+    {
+        let slice = self.slice;
+        let (quoted, start) = match self.decode_at(self.index)? {
+            Some((b'\'', len)) => (true, self.index + len),
+            _ => (false, self.index),
+        };
+
+        let mut end = start;
+        while let Some((b, len)) = self.decode_at(end)? {
+            if quoted && b == b'\'' {
+                self.index = end + len;
+                return result(self, &slice[start..end]).map(Reference::Borrowed);
+            }
+            if !quoted && ends_id(b, single_line) {
+                break;
+            }
+            if (quoted && b == b'!') || self.is_encoded(slice[end]) {
+                // The string itself needs decoding or unescaping.
+                return self.parse_str_copied(scratch, max_len, single_line, result);
+            }
+            end += len;
+        }
+        if !quoted {
+            self.index = end;
+            return result(self, &slice[start..end]).map(Reference::Borrowed);
         }
+
+        self.parse_str_copied(scratch, max_len, single_line, result)
+    }
+
+    fn parse_str_copied<'s, T, F>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+        result: F,
+    ) -> Result<Reference<'a, 's, T>>
+    where
+        T: ?Sized + 's,
+        F: for<'f> FnOnce(&'s Self, &'f [u8]) -> Result<&'f T>,
+    {
+        parse_str_bytes(self, scratch, max_len, single_line)?;
+        result(self, scratch).map(Reference::Copied)
+    }
+}
+
+impl<'a> Read<'a> for PercentDecodeRead<'a> {
+    fn next(&mut self) -> Result<Option<u8>> {
+        Ok(self.decode_at(self.index)?.map(|(b, len)| {
+            self.index += len;
+            b
+        }))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.decode_at(self.index)?.map(|(b, _)| b))
+    }
+
+    fn peek_second(&mut self) -> Result<Option<u8>> {
+        match self.decode_at(self.index)? {
+            Some((_, len)) => Ok(self.decode_at(self.index + len)?.map(|(b, _)| b)),
+            None => Ok(None),
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.index
+    }
+
+    fn eat_char(&mut self) {
+        self.index += if self.slice[self.index] == b'%' { 3 } else { 1 };
+    }
+
+    fn parse_str<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'a, 's, str>> {
+        self.parse_str_bytes(scratch, usize::MAX, false, as_str)
+    }
+
+    fn parse_str_raw<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'a, 's, [u8]>> {
+        self.parse_str_bytes(scratch, usize::MAX, false, |_, bytes| Ok(bytes))
+    }
+
+    fn parse_str_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'a, 's, str>> {
+        self.parse_str_bytes(scratch, max_len, single_line, as_str)
+    }
+
+    fn parse_str_raw_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'a, 's, [u8]>> {
+        self.parse_str_bytes(scratch, max_len, single_line, |_, bytes| Ok(bytes))
+    }
+}
+
+fn decode_hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "std")]
+/// Rison input source that reads from a `std::io::Read`.
+///
+/// Bytes are pulled from the reader one at a time, so an unbuffered source
+/// such as a `File` should be wrapped in a `std::io::BufReader`.
+pub struct IoRead<R>
+where
+    R: io::Read,
+{
+    iter: io::Bytes<R>,
+    /// Bytes already taken from `iter` by `peek` and `peek_second`, but not
+    /// consumed yet.
+    lookahead: [u8; 2],
+    lookahead_len: usize,
+    index: usize,
+}
+
+#[cfg(feature = "std")]
+impl<R> IoRead<R>
+where
+    R: io::Read,
+{
+    /// Creates a rison input source to read from a `std::io::Read`.
+    // Buffering is left to the caller, see the type level docs.
+    #[allow(clippy::unbuffered_bytes)]
+    pub fn new(reader: R) -> Self {
+        IoRead {
+            iter: reader.bytes(),
+            lookahead: [0; 2],
+            lookahead_len: 0,
+            index: 0,
+        }
+    }
+
+    /// Makes sure at least `n` bytes are buffered, unless the input ends
+    /// first.
+    fn fill(&mut self, n: usize) -> Result<()> {
+        while self.lookahead_len < n {
+            match self.iter.next() {
+                Some(Ok(b)) => {
+                    self.lookahead[self.lookahead_len] = b;
+                    self.lookahead_len += 1;
+                }
+                Some(Err(err)) => return Err(error(self, ErrorCode::Io(err))),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, R> Read<'de> for IoRead<R>
+where
+    R: io::Read,
+{
+    fn next(&mut self) -> Result<Option<u8>> {
+        self.fill(1)?;
+        if self.lookahead_len == 0 {
+            return Ok(None);
+        }
+        let ch = self.lookahead[0];
+        self.eat_char();
+        Ok(Some(ch))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        self.fill(1)?;
+        Ok(self.lookahead[..self.lookahead_len].first().copied())
+    }
+
+    fn peek_second(&mut self) -> Result<Option<u8>> {
+        self.fill(2)?;
+        Ok(self.lookahead[..self.lookahead_len].get(1).copied())
     }
 
     fn position(&self) -> usize {
@@ -47,6 +554,148 @@ impl<'a> Read for SliceRead<'a> {
     }
 
     fn eat_char(&mut self) {
+        self.lookahead[0] = self.lookahead[1];
+        self.lookahead_len -= 1;
         self.index += 1;
     }
+
+    fn parse_str_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, str>> {
+        parse_str_bytes(self, scratch, max_len, single_line)?;
+        as_str(self, scratch).map(Reference::Copied)
+    }
+
+    fn parse_str_raw_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        parse_str_bytes(self, scratch, max_len, single_line)?;
+        Ok(Reference::Copied(scratch))
+    }
+}
+
+impl<'de, R> Read<'de> for &mut R
+where
+    R: ?Sized + Read<'de>,
+{
+    fn next(&mut self) -> Result<Option<u8>> {
+        R::next(self)
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        R::peek(self)
+    }
+
+    fn peek_second(&mut self) -> Result<Option<u8>> {
+        R::peek_second(self)
+    }
+
+    fn position(&self) -> usize {
+        R::position(self)
+    }
+
+    fn eat_char(&mut self) {
+        R::eat_char(self)
+    }
+
+    fn parse_str<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's, str>> {
+        R::parse_str(self, scratch)
+    }
+
+    fn parse_str_raw<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        R::parse_str_raw(self, scratch)
+    }
+
+    fn parse_str_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, str>> {
+        R::parse_str_limited(self, scratch, max_len, single_line)
+    }
+
+    fn parse_str_raw_limited<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+        max_len: usize,
+        single_line: bool,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        R::parse_str_raw_limited(self, scratch, max_len, single_line)
+    }
+}
+
+/// Whether `b` ends an id, as a line break does too with `single_line`.
+fn ends_id(b: u8, single_line: bool) -> bool {
+    NOT_ID[b as usize] || (single_line && matches!(b, b'\n' | b'\r'))
+}
+
+/// Copying counterpart of `SliceRead::parse_str_bytes`, written against the
+/// byte level methods only so that it works for any source. Stops reading
+/// once `scratch` grows past `max_len`.
+fn parse_str_bytes<'de, R>(
+    read: &mut R,
+    scratch: &mut Vec<u8>,
+    max_len: usize,
+    single_line: bool,
+) -> Result<()>
+where
+    R: ?Sized + Read<'de>,
+{
+    scratch.clear();
+
+    if read.peek()? != Some(b'\'') {
+        while let Some(b) = read.peek()? {
+            if ends_id(b, single_line) {
+                break;
+            }
+            if scratch.len() == max_len {
+                return Err(error(read, ErrorCode::StringTooLong));
+            }
+            read.eat_char();
+            scratch.push(b);
+        }
+        return Ok(());
+    }
+
+    read.eat_char();
+    loop {
+        let b = match read.next()? {
+            Some(b'\'') => return Ok(()),
+            Some(b'!') => match read.next()? {
+                Some(b @ (b'!' | b'\'')) => b,
+                Some(b) => return Err(error(read, ErrorCode::InvalidEscape(b))),
+                None => return Err(error(read, ErrorCode::EofWhileParsingString)),
+            },
+            Some(b) => b,
+            None => return Err(error(read, ErrorCode::EofWhileParsingString)),
+        };
+        if scratch.len() == max_len {
+            return Err(error(read, ErrorCode::StringTooLong));
+        }
+        scratch.push(b);
+    }
+}
+
+fn error<'de, R>(read: &R, code: ErrorCode) -> Error
+where
+    R: ?Sized + Read<'de>,
+{
+    Error::syntax(code, read.position())
+}
+
+fn as_str<'de, 's, R>(read: &R, slice: &'s [u8]) -> Result<&'s str>
+where
+    R: ?Sized + Read<'de>,
+{
+    str::from_utf8(slice).map_err(|_| error(read, ErrorCode::InvalidUnicode))
 }
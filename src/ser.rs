@@ -1,24 +1,105 @@
+use crate::path::{Path, Segment};
+use crate::DuplicateKeys;
 use alloc::{
     boxed::Box,
-    collections::BTreeMap,
     string::{String, ToString},
+    vec::Vec,
 };
-use core::{fmt, num::FpCategory, result};
+use core::{cmp::Ordering, fmt, mem, num::FpCategory, ops::Range, result};
 use serde::ser::{self, Impossible, Serialize};
+#[cfg(feature = "std")]
+use std::io;
+
+/// An error that happened while serializing, with the path to the value
+/// it happened in.
+pub struct Error {
+    code: ErrorCode,
+    path: Path,
+}
 
-pub enum Error {
+pub(crate) enum ErrorCode {
     Message(Box<str>),
-    KeyMustBeAString,
+    /// A map key serialized as something other than a string, naming what
+    /// it was instead.
+    KeyMustBeAString(&'static str),
+    OrisonMustBeAMap,
+    ArisonMustBeASeq,
+    /// A map has the key more than once, with `DuplicateKeys::Error`.
+    DuplicateKey(Box<str>),
+    /// The underlying `fmt::Write` failed.
+    Fmt,
+    #[cfg(feature = "std")]
+    Io(io::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
-impl fmt::Display for Error {
+impl Error {
+    pub(crate) fn new(code: ErrorCode) -> Self {
+        Error {
+            code,
+            path: Path::default(),
+        }
+    }
+
+    /// The path to the value the error happened in, like
+    /// `panels[3].gridData.w`, or an empty string for the top-level value.
+    pub fn path(&self) -> String {
+        self.path.to_string()
+    }
+
+    /// Whether the error came from the underlying writer rather than from
+    /// the value being serialized.
+    pub fn is_io(&self) -> bool {
+        match self.code {
+            ErrorCode::Fmt => true,
+            #[cfg(feature = "std")]
+            ErrorCode::Io(_) => true,
+            _ => false,
+        }
+    }
+
+    fn at(mut self, segment: Segment) -> Self {
+        self.path.push(segment);
+        self
+    }
+}
+
+pub(crate) fn key_must_be_a_string(found: &'static str) -> Error {
+    Error::new(ErrorCode::KeyMustBeAString(found))
+}
+
+fn orison_must_be_a_map() -> Error {
+    Error::new(ErrorCode::OrisonMustBeAMap)
+}
+
+fn arison_must_be_a_seq() -> Error {
+    Error::new(ErrorCode::ArisonMustBeASeq)
+}
+
+impl fmt::Display for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Message(msg) => f.write_str(msg),
-            Error::KeyMustBeAString => f.write_str("key must be a string"),
+            ErrorCode::Message(msg) => f.write_str(msg),
+            ErrorCode::KeyMustBeAString(found) => {
+                write!(f, "key must be a string, found {}", found)
+            }
+            ErrorCode::OrisonMustBeAMap => f.write_str("O-Rison value must be a struct or map"),
+            ErrorCode::ArisonMustBeASeq => f.write_str("A-Rison value must be a sequence"),
+            ErrorCode::DuplicateKey(key) => write!(f, "duplicate key `{}`", key),
+            ErrorCode::Fmt => f.write_str("error writing rison to the output"),
+            #[cfg(feature = "std")]
+            ErrorCode::Io(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
         }
+        fmt::Display::fmt(&self.code, f)
     }
 }
 
@@ -28,88 +109,308 @@ impl fmt::Debug for Error {
     }
 }
 
-impl ser::StdError for Error {}
+impl ser::StdError for Error {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn ser::StdError + 'static)> {
+        match &self.code {
+            ErrorCode::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl ser::Error for Error {
     fn custom<T>(msg: T) -> Self
     where
         T: fmt::Display,
     {
-        Error::Message(msg.to_string().into_boxed_str())
+        Error::new(ErrorCode::Message(msg.to_string().into_boxed_str()))
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::new(ErrorCode::Fmt)
+    }
+}
+
+/// A rison serializer writing into any `fmt::Write`, such as a `String` or a
+/// `fmt::Formatter`.
+pub struct Serializer<W> {
+    writer: W,
+    /// Percent-encode the output so that it can be put in a URI as is.
+    uri: bool,
+    /// Quote strings with line breaks, to keep each value on one line.
+    quote_line_breaks: bool,
+    key_order: KeyOrder,
+    duplicate_keys: DuplicateKeys,
+    arena: Arena,
+    #[cfg(feature = "unbounded_depth")]
+    grow_stack: bool,
+}
+
+#[cfg(feature = "std")]
+impl<W> Serializer<IoWriter<W>>
+where
+    W: io::Write,
+{
+    /// Creates a rison serializer writing into an `io::Write`, to be
+    /// configured like any other before calling `serialize_io`.
+    pub fn from_io(writer: W) -> Self {
+        Serializer::new(IoWriter::new(writer))
+    }
+
+    /// Serializes the value into the writer. Unlike `value.serialize(&mut
+    /// ser)`, which can only report that the writer failed, the error then
+    /// carries the `io::Error`.
+    pub fn serialize_io<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut *self).map_err(|mut err| {
+            if let (ErrorCode::Fmt, Some(io)) = (&err.code, self.writer.take_error()) {
+                err.code = ErrorCode::Io(io);
+            }
+            err
+        })
+    }
+}
+
+/// The order in which the entries of a map or struct are written.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum KeyOrder {
+    /// Sort by the key as written, quotes and escapes included. Quoted keys
+    /// like `'a b'` come before ids. This is the default.
+    #[default]
+    Escaped,
+    /// Sort by the unescaped key, like rison-node.
+    Raw,
+    /// Keep the order in which the entries are serialized: declaration order
+    /// for structs and iteration order for maps.
+    Insertion,
+    /// Sort the unescaped keys with the given comparator.
+    Custom(fn(&str, &str) -> Ordering),
+}
+
+impl<W> Serializer<W>
+where
+    W: fmt::Write,
+{
+    pub fn new(writer: W) -> Self {
+        Serializer {
+            writer,
+            uri: false,
+            quote_line_breaks: false,
+            key_order: KeyOrder::default(),
+            duplicate_keys: DuplicateKeys::default(),
+            arena: Arena::default(),
+            #[cfg(feature = "unbounded_depth")]
+            grow_stack: false,
+        }
+    }
+
+    /// Grows the stack on demand while serializing nested values, so that
+    /// values nested deeper than the call stack allows can be serialized.
+    #[cfg(feature = "unbounded_depth")]
+    pub fn grow_stack(mut self) -> Self {
+        self.grow_stack = true;
+        self
+    }
+
+    /// Percent-encodes the characters that are unsafe in a URI, and writes
+    /// spaces as `+`, like `to_string_uri`.
+    pub fn uri(mut self) -> Self {
+        self.uri = true;
+        self
+    }
+
+    /// Sets the order in which map and struct entries are written.
+    pub fn key_order(mut self, order: KeyOrder) -> Self {
+        self.key_order = order;
+        self
+    }
+
+    /// Sets what to do with a key that is serialized twice in the same map,
+    /// which can happen with `#[serde(flatten)]` or a custom `Serialize`.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> Self {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Unwraps the writer from the serializer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Where the output goes: the arena while inside a map, whose entries
+    /// still have to be sorted, and the writer otherwise.
+    fn out(&mut self) -> &mut dyn fmt::Write {
+        if self.arena.depth == 0 {
+            &mut self.writer
+        } else {
+            &mut self.arena
+        }
+    }
+
+    /// Serializes a value nested in a sequence, map or variant.
+    fn serialize_nested<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        #[cfg(feature = "unbounded_depth")]
+        if self.grow_stack {
+            return crate::maybe_grow_stack(|| value.serialize(self));
+        }
+        value.serialize(self)
+    }
+
+    fn push_escaped_str(&mut self, value: &str) -> fmt::Result {
+        let quote = self.quote_line_breaks;
+        if self.uri {
+            escaped_str_with(&mut UriEncoder(self.out()), value, quote)
+        } else {
+            escaped_str_with(self.out(), value, quote)
+        }
     }
 }
 
-pub struct Serializer {
+/// Buffers the output of maps until their entries can be sorted by key.
+///
+/// Text is appended to `buf` once and never moved, and `spans` are ranges of
+/// `buf` linked in output order. Sorting a map only relinks its entries, so
+/// nested maps cost no extra copying and the text is put together once, when
+/// the outermost map ends.
+#[derive(Default)]
+struct Arena {
     buf: String,
+    spans: Vec<Span>,
+    /// Number of maps currently being serialized.
+    depth: usize,
+}
+
+struct Span {
+    text: Range<usize>,
+    /// Index of the span that follows this one in the output, which is the
+    /// next one in `spans` unless a map was reordered.
+    next: usize,
+}
+
+impl Arena {
+    /// Adds the text of `buf` from `start` as a span of its own.
+    fn push_span_from(&mut self, start: usize) {
+        self.spans.push(Span {
+            text: start..self.buf.len(),
+            next: self.spans.len() + 1,
+        });
+    }
+
+    /// Appends `s` as a span of its own.
+    fn push_span(&mut self, s: &str) {
+        let start = self.buf.len();
+        self.buf.push_str(s);
+        self.push_span_from(start);
+    }
+
+    /// Index of the last span, where the output currently ends.
+    fn last_span(&self) -> usize {
+        self.spans.len() - 1
+    }
+
+    fn write_spans<W: fmt::Write>(&mut self, writer: &mut W) -> fmt::Result {
+        let mut result = Ok(());
+        let mut i = 0;
+        while let Some(span) = self.spans.get(i) {
+            result = writer.write_str(&self.buf[span.text.clone()]);
+            if result.is_err() {
+                break;
+            }
+            i = span.next;
+        }
+        self.spans.clear();
+        self.buf.clear();
+        result
+    }
+}
+
+impl fmt::Write for Arena {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.buf.len();
+        self.buf.push_str(s);
+        match self.spans.last_mut() {
+            Some(last) if last.text.end == start => last.text.end = self.buf.len(),
+            _ => self.push_span_from(start),
+        }
+        Ok(())
+    }
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl<'a, W: fmt::Write> ser::Serializer for &'a mut Serializer<W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = SeqSerializer<'a>;
-    type SerializeTuple = SeqSerializer<'a>;
-    type SerializeTupleStruct = SeqSerializer<'a>;
-    type SerializeTupleVariant = SeqSerializer<'a>;
-    type SerializeMap = MapSerializer<'a>;
-    type SerializeStruct = MapSerializer<'a>;
-    type SerializeStructVariant = MapSerializer<'a>;
+    type SerializeSeq = SeqSerializer<'a, W>;
+    type SerializeTuple = SeqSerializer<'a, W>;
+    type SerializeTupleStruct = SeqSerializer<'a, W>;
+    type SerializeTupleVariant = SeqSerializer<'a, W>;
+    type SerializeMap = MapSerializer<'a, W>;
+    type SerializeStruct = MapSerializer<'a, W>;
+    type SerializeStructVariant = MapSerializer<'a, W>;
 
     fn serialize_bool(self, v: bool) -> Result<()> {
         if v {
-            self.buf.push_str("!t");
+            self.out().write_str("!t")?;
         } else {
-            self.buf.push_str("!f");
+            self.out().write_str("!f")?;
         }
         Ok(())
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_i128(self, v: i128) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
     fn serialize_u128(self, v: u128) -> Result<()> {
-        int_to_string(&mut self.buf, v);
+        int_to_string(self.out(), v)?;
         Ok(())
     }
 
@@ -117,7 +418,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         match v.classify() {
             FpCategory::Nan | FpCategory::Infinite => self.serialize_unit(),
             _ => {
-                float_to_string(&mut self.buf, v);
+                float_to_string(self.out(), v)?;
                 Ok(())
             }
         }
@@ -127,7 +428,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         match v.classify() {
             FpCategory::Nan | FpCategory::Infinite => self.serialize_unit(),
             _ => {
-                float_to_string(&mut self.buf, v);
+                float_to_string(self.out(), v)?;
                 Ok(())
             }
         }
@@ -139,15 +440,15 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        escaped_str(&mut self.buf, v);
+        self.push_escaped_str(v)?;
         Ok(())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
         use serde::ser::SerializeSeq;
-        let mut seq = self.serialize_seq(Some(v.len())).unwrap();
+        let mut seq = self.serialize_seq(Some(v.len()))?;
         for b in v {
-            seq.serialize_element(b).unwrap();
+            seq.serialize_element(b)?;
         }
         seq.end()
     }
@@ -156,15 +457,15 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_unit()
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<()>
+    fn serialize_some<T>(self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<()> {
-        self.buf.push_str("!n");
+        self.out().write_str("!n")?;
         Ok(())
     }
 
@@ -181,14 +482,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str(variant)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -196,18 +497,18 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         value: &T,
     ) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.buf.push('(');
-        self.serialize_str(variant).unwrap();
-        self.buf.push(':');
-        value.serialize(&mut *self)?;
-        self.buf.push(')');
+        self.out().write_char('(')?;
+        self.serialize_str(variant)?;
+        self.out().write_char(':')?;
+        self.serialize_nested(value)?;
+        self.out().write_char(')')?;
         Ok(())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        self.buf.push_str("!(");
+        self.out().write_str("!(")?;
         Ok(SeqSerializer::new(self))
     }
 
@@ -230,14 +531,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.buf.push('(');
-        self.serialize_str(variant).unwrap();
-        self.buf.push(':');
+        self.out().write_char('(')?;
+        self.serialize_str(variant)?;
+        self.out().write_char(':')?;
         self.serialize_seq(Some(len))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Ok(MapSerializer::new(self))
+        Ok(MapSerializer::new(self, false))
     }
 
     fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
@@ -251,333 +552,479 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.buf.push('(');
-        self.serialize_str(variant).unwrap();
-        self.buf.push(':');
+        self.out().write_char('(')?;
+        self.serialize_str(variant)?;
+        self.out().write_char(':')?;
         self.serialize_map(Some(len))
     }
 }
 
-impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeSeq for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.write_sep();
-        value.serialize(&mut *self.ser)
+        self.serialize_item(value)
     }
 
     fn end(self) -> Result<()> {
-        self.ser.buf.push(')');
-        Ok(())
+        self.write_end()
     }
 }
 
-impl<'a> ser::SerializeTuple for SeqSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeTuple for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.write_sep();
-        value.serialize(&mut *self.ser)
+        self.serialize_item(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.ser.buf.push(')');
-        Ok(())
+        self.write_end()
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for SeqSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeTupleStruct for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.write_sep();
-        value.serialize(&mut *self.ser)
+        self.serialize_item(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.ser.buf.push(')');
-        Ok(())
+        self.write_end()
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for SeqSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeTupleVariant for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.write_sep();
-        value.serialize(&mut *self.ser)
+        self.serialize_item(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.ser.buf.push_str("))");
+        self.ser.out().write_str("))")?;
         Ok(())
     }
 }
 
 #[doc(hidden)]
-pub struct SeqSerializer<'a> {
-    ser: &'a mut Serializer,
-    first: bool,
+pub struct SeqSerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
+    /// Index of the next element, for error paths.
+    index: usize,
+    /// Leave out the enclosing `!(` and `)`, for A-Rison.
+    bare: bool,
 }
 
-impl<'a> SeqSerializer<'a> {
-    fn new(ser: &'a mut Serializer) -> SeqSerializer<'a> {
-        SeqSerializer { ser, first: true }
+impl<'a, W: fmt::Write> SeqSerializer<'a, W> {
+    fn new(ser: &'a mut Serializer<W>) -> SeqSerializer<'a, W> {
+        SeqSerializer {
+            ser,
+            index: 0,
+            bare: false,
+        }
     }
 
-    fn write_sep(&mut self) {
-        if self.first {
-            self.first = false;
-        } else {
-            self.ser.buf.push(',');
+    fn write_end(self) -> Result<()> {
+        if !self.bare {
+            self.ser.out().write_char(')')?;
+        }
+        Ok(())
+    }
+
+    fn serialize_item<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.index != 0 {
+            self.ser.out().write_char(',')?;
         }
+        self.ser
+            .serialize_nested(value)
+            .map_err(|err| err.at(Segment::Index(self.index)))?;
+        self.index += 1;
+        Ok(())
     }
 }
 
 #[doc(hidden)]
-pub struct MapSerializer<'a> {
-    ser: &'a mut Serializer,
-    map: BTreeMap<String, String>,
-    key: Option<String>,
+pub struct MapSerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
+    /// Index of the span opening this map, which is empty for O-Rison.
+    open: usize,
+    entries: Vec<Entry>,
+    /// Leave out the enclosing parentheses, for O-Rison.
+    bare: bool,
+    done: bool,
+}
+
+/// An entry of a map being serialized: the escaped key in the arena's
+/// buffer, and the first and last of the arena spans holding `key:value`.
+struct Entry {
+    key: Range<usize>,
+    /// The unescaped key, when it differs from the escaped one and the key
+    /// order needs it.
+    raw: Option<String>,
+    first: usize,
+    last: usize,
+}
+
+impl Entry {
+    fn escaped<'a>(&self, buf: &'a str) -> &'a str {
+        &buf[self.key.clone()]
+    }
+
+    fn raw<'a>(&'a self, buf: &'a str) -> &'a str {
+        self.raw.as_deref().unwrap_or_else(|| self.escaped(buf))
+    }
 }
 
-impl<'a> MapSerializer<'a> {
-    fn new(ser: &'a mut Serializer) -> MapSerializer<'a> {
+impl<'a, W: fmt::Write> MapSerializer<'a, W> {
+    fn new(ser: &'a mut Serializer<W>, bare: bool) -> MapSerializer<'a, W> {
+        ser.arena.depth += 1;
+        ser.arena.push_span(if bare { "" } else { "(" });
+        let open = ser.arena.last_span();
         MapSerializer {
             ser,
-            map: BTreeMap::new(),
-            key: None,
+            open,
+            entries: Vec::new(),
+            bare,
+            done: false,
+        }
+    }
+
+    /// Starts an entry whose escaped key was just written to the arena's
+    /// buffer from `key_start`.
+    fn begin_entry(&mut self, key_start: usize) -> Result<()> {
+        let arena = &mut self.ser.arena;
+        let key = key_start..arena.buf.len();
+        let raw = match self.ser.key_order {
+            KeyOrder::Raw | KeyOrder::Custom(_) => unescape_key(&arena.buf[key.clone()]),
+            KeyOrder::Escaped | KeyOrder::Insertion => None,
+        };
+        if self.ser.uri {
+            // The escaped key stays in the buffer for sorting, and its
+            // encoded form is what gets written.
+            let start = arena.buf.len();
+            for i in key.clone() {
+                let b = arena.buf.as_bytes()[i];
+                encode_uri_byte(&mut arena.buf, b)?;
+            }
+            arena.push_span_from(start);
+        } else {
+            arena.push_span_from(key.start);
         }
+        fmt::Write::write_char(arena, ':')?;
+        let first = arena.last_span();
+        self.entries.push(Entry {
+            key,
+            raw,
+            first,
+            last: first,
+        });
+        Ok(())
+    }
+
+    fn serialize_entry_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Err(err) = self.ser.serialize_nested(value) {
+            let escaped = self.entries.last().unwrap().escaped(&self.ser.arena.buf);
+            let key = unescape_key(escaped).unwrap_or_else(|| escaped.to_string());
+            return Err(err.at(Segment::Key(key.into_boxed_str())));
+        }
+        let last = self.ser.arena.last_span();
+        self.entries.last_mut().unwrap().last = last;
+        Ok(())
     }
 
-    fn write_object(self, end: &str) {
-        self.ser.buf.push('(');
-        for (i, (key, value)) in self.map.iter().enumerate() {
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key_start = self.ser.arena.buf.len();
+        escaped_str_with(&mut self.ser.arena.buf, key, self.ser.quote_line_breaks)?;
+        self.begin_entry(key_start)?;
+        self.serialize_entry_value(value)
+    }
+
+    fn write_object(mut self, close: &str) -> Result<()> {
+        let ser = &mut *self.ser;
+        let arena = &mut ser.arena;
+        let buf = &arena.buf;
+        // Duplicate keys are found by sorting on the escaped key, whatever
+        // order the entries are written in. The sorts are stable, so
+        // duplicates stay in the order they were serialized in.
+        self.entries
+            .sort_by(|a, b| a.escaped(buf).cmp(b.escaped(buf)));
+        dedup_entries(&mut self.entries, buf, ser.duplicate_keys)?;
+        match ser.key_order {
+            KeyOrder::Escaped => {}
+            // Spans are allocated in serialization order.
+            KeyOrder::Insertion => self.entries.sort_by_key(|entry| entry.first),
+            KeyOrder::Raw => self.entries.sort_by(|a, b| a.raw(buf).cmp(b.raw(buf))),
+            KeyOrder::Custom(cmp) => {
+                // Keys the comparator finds equal keep their insertion order.
+                self.entries.sort_by_key(|entry| entry.first);
+                self.entries.sort_by(|a, b| cmp(a.raw(buf), b.raw(buf)));
+            }
+        }
+
+        // Link the entries in their new order, with separators between them.
+        let mut prev = self.open;
+        for (i, entry) in self.entries.iter().enumerate() {
             if i != 0 {
-                self.ser.buf.push(',');
+                arena.push_span(",");
+                arena.spans[prev].next = arena.last_span();
+                prev = arena.last_span();
+            }
+            arena.spans[prev].next = entry.first;
+            prev = entry.last;
+        }
+        arena.push_span(if self.bare { "" } else { close });
+        arena.spans[prev].next = arena.last_span();
+
+        self.done = true;
+        arena.depth -= 1;
+        if arena.depth == 0 {
+            arena.write_spans(&mut ser.writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the duplicate key policy to entries in which equal keys are next
+/// to each other, in the order they were serialized.
+fn dedup_entries(entries: &mut Vec<Entry>, buf: &str, policy: DuplicateKeys) -> Result<()> {
+    let mut result = Ok(());
+    entries.dedup_by(|later, earlier| {
+        if later.escaped(buf) != earlier.escaped(buf) {
+            return false;
+        }
+        match policy {
+            DuplicateKeys::Error => {
+                if result.is_ok() {
+                    let escaped = later.escaped(buf);
+                    let key = unescape_key(escaped).unwrap_or_else(|| escaped.to_string());
+                    result = Err(Error::new(ErrorCode::DuplicateKey(key.into_boxed_str())));
+                }
             }
-            self.ser.buf.push_str(key.as_str());
-            self.ser.buf.push(':');
-            self.ser.buf.push_str(value.as_str());
+            DuplicateKeys::FirstWins => {}
+            DuplicateKeys::LastWins => mem::swap(later, earlier),
+        }
+        true
+    });
+    result
+}
+
+impl<W> Drop for MapSerializer<'_, W> {
+    fn drop(&mut self) {
+        // The map failed to serialize. Throw away what it left in the arena,
+        // so that the serializer can be used again.
+        if !self.done {
+            let arena = &mut self.ser.arena;
+            arena.buf.truncate(arena.spans[self.open].text.start);
+            arena.spans.truncate(self.open);
+            arena.depth -= 1;
         }
-        self.ser.buf.push_str(end);
     }
 }
 
-impl<'a> ser::SerializeMap for MapSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeMap for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        let mut ser = MapKeySerializer {
-            buf: String::with_capacity(4),
-        };
-        key.serialize(&mut ser)?;
-        self.key = Some(ser.buf);
-        Ok(())
+        let key_start = self.ser.arena.buf.len();
+        key.serialize(&mut MapKeySerializer {
+            buf: &mut self.ser.arena.buf,
+            quote_line_breaks: self.ser.quote_line_breaks,
+        })?;
+        self.begin_entry(key_start)
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.map.insert(self.key.take().unwrap(), to_string(value)?);
-        Ok(())
+        self.serialize_entry_value(value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.write_object(")");
-        Ok(())
+        self.write_object(")")
     }
 }
 
-impl<'a> ser::SerializeStruct for MapSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeStruct for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        let mut buf = String::with_capacity(key.len());
-        escaped_str(&mut buf, key);
-        self.map.insert(buf, to_string(value)?);
-        Ok(())
+        MapSerializer::serialize_field(self, key, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.write_object(")");
-        Ok(())
+        self.write_object(")")
     }
 }
 
-impl<'a> ser::SerializeStructVariant for MapSerializer<'a> {
+impl<'a, W: fmt::Write> ser::SerializeStructVariant for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        let mut buf = String::with_capacity(key.len());
-        escaped_str(&mut buf, key);
-        self.map.insert(buf, to_string(value)?);
-        Ok(())
+        MapSerializer::serialize_field(self, key, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.write_object("))");
-        Ok(())
+        self.write_object("))")
     }
 }
 
-struct MapKeySerializer {
-    buf: String,
+/// Serializes the top-level value as O-Rison, a map without the enclosing
+/// parentheses.
+struct OrisonSerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
 }
 
-impl<'a> ser::Serializer for &'a mut MapKeySerializer {
+impl<'a, W: fmt::Write> ser::Serializer for OrisonSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
     type SerializeSeq = Impossible<(), Error>;
     type SerializeTuple = Impossible<(), Error>;
     type SerializeTupleStruct = Impossible<(), Error>;
     type SerializeTupleVariant = Impossible<(), Error>;
-    type SerializeMap = Impossible<(), Error>;
-    type SerializeStruct = Impossible<(), Error>;
+    type SerializeMap = MapSerializer<'a, W>;
+    type SerializeStruct = MapSerializer<'a, W>;
     type SerializeStructVariant = Impossible<(), Error>;
 
     fn serialize_bool(self, _v: bool) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_i8(self, v: i8) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_i16(self, v: i16) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_i32(self, v: i32) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_i32(self, _v: i32) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_i64(self, v: i64) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_i128(self, v: i128) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_i128(self, _v: i128) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_u8(self, v: u8) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_u8(self, _v: u8) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_u16(self, v: u16) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_u32(self, v: u32) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_u32(self, _v: u32) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_u64(self, v: u64) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_u128(self, v: u128) -> Result<()> {
-        int_to_string(&mut self.buf, v);
-        Ok(())
+    fn serialize_u128(self, _v: u128) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_f32(self, _v: f32) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_f64(self, _v: f64) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_char(self, v: char) -> Result<()> {
-        let mut buf = [0; 4];
-        self.serialize_str(v.encode_utf8(&mut buf))
+    fn serialize_char(self, _v: char) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_str(self, v: &str) -> Result<()> {
-        escaped_str(&mut self.buf, v);
-        Ok(())
+    fn serialize_str(self, _v: &str) -> Result<()> {
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_none(self) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<()>
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_unit(self) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        variant: &'static str,
+        _variant: &'static str,
     ) -> Result<()> {
-        escaped_str(&mut self.buf, variant);
-        Ok(())
+        Err(orison_must_be_a_map())
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -585,17 +1032,17 @@ impl<'a> ser::Serializer for &'a mut MapKeySerializer {
         _value: &T,
     ) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_tuple_struct(
@@ -603,7 +1050,7 @@ impl<'a> ser::Serializer for &'a mut MapKeySerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_tuple_variant(
@@ -613,15 +1060,15 @@ impl<'a> ser::Serializer for &'a mut MapKeySerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Err(Error::KeyMustBeAString)
+        Ok(MapSerializer::new(self.ser, true))
     }
 
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Err(Error::KeyMustBeAString)
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
     }
 
     fn serialize_struct_variant(
@@ -631,69 +1078,526 @@ impl<'a> ser::Serializer for &'a mut MapKeySerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::KeyMustBeAString)
+        Err(orison_must_be_a_map())
     }
 }
 
-#[inline]
-fn int_to_string<I: itoa::Integer>(s: &mut String, i: I) {
-    use itoa::Buffer;
-    let mut buf = Buffer::new();
-    s.push_str(buf.format(i));
+/// Serializes the top-level value as A-Rison, a sequence without the
+/// enclosing `!(` and `)`.
+struct ArisonSerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
 }
 
-#[inline]
-fn float_to_string<F: ryu::Float>(s: &mut String, f: F) {
-    use ryu::Buffer;
-    let mut buf = Buffer::new();
-    s.push_str(buf.format(f))
-}
+impl<'a, W: fmt::Write> ser::Serializer for ArisonSerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SeqSerializer<'a, W>;
+    type SerializeTuple = SeqSerializer<'a, W>;
+    type SerializeTupleStruct = SeqSerializer<'a, W>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
 
-fn escaped_str(s: &mut String, value: &str) {
-    let bytes = value.as_bytes();
+    fn serialize_bool(self, _v: bool) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
 
-    if bytes.is_empty() {
-        s.push_str("''");
-        return;
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(arison_must_be_a_seq())
     }
 
-    if !NOT_ID_START[bytes[0] as usize] && !bytes[1..].iter().any(|b| NOT_ID[*b as usize]) {
-        s.push_str(value);
-        return;
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(arison_must_be_a_seq())
     }
 
-    s.push('\'');
-    let mut start = 0;
-    for (i, &b) in bytes.iter().enumerate() {
-        if b != b'!' && b != b'\'' {
-            continue;
-        }
+    fn serialize_i32(self, _v: i32) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
 
-        if start < i {
-            s.push_str(&value[start..i]);
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_i128(self, _v: i128) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_u128(self, _v: u128) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_char(self, _v: char) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        use serde::ser::SerializeSeq;
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for b in v {
+            seq.serialize_element(b)?;
         }
-        s.push('!');
-        s.push(b.into());
+        seq.end()
+    }
 
-        start = i + 1;
+    fn serialize_none(self) -> Result<()> {
+        Err(arison_must_be_a_seq())
     }
-    if start < bytes.len() {
-        s.push_str(&value[start..]);
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(arison_must_be_a_seq())
     }
-    s.push('\'');
-}
 
-const T: bool = true;
-const F: bool = false;
-// Lookup table: " '!:(),*@$" are true
-static NOT_ID: [bool; 256] = [
-    // 1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
-    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 0
-    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 1
-    T, T, F, F, T, F, F, T, T, T, T, F, T, F, F, F, // 2
-    F, F, F, F, F, F, F, F, F, F, T, F, F, F, F, F, // 3
-    T, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 4
-    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 5
+    fn serialize_unit(self) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        let mut seq = SeqSerializer::new(self.ser);
+        seq.bare = true;
+        Ok(seq)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(arison_must_be_a_seq())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(arison_must_be_a_seq())
+    }
+}
+
+struct MapKeySerializer<'a> {
+    buf: &'a mut String,
+    quote_line_breaks: bool,
+}
+
+impl ser::Serializer for &mut MapKeySerializer<'_> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<()> {
+        Err(key_must_be_a_string("bool"))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        int_to_string(&mut self.buf, v)?;
+        Ok(())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(key_must_be_a_string("f32"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(key_must_be_a_string("f64"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        let mut buf = [0; 4];
+        self.serialize_str(v.encode_utf8(&mut buf))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        escaped_str_with(&mut self.buf, v, self.quote_line_breaks)?;
+        Ok(())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
+        Err(key_must_be_a_string("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(key_must_be_a_string("none"))
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string("option"))
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(key_must_be_a_string("unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Err(key_must_be_a_string("unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string("newtype variant"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_must_be_a_string("sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_must_be_a_string("tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_must_be_a_string("tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_must_be_a_string("tuple variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_must_be_a_string("map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_must_be_a_string("struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_must_be_a_string("struct variant"))
+    }
+}
+
+#[inline]
+fn int_to_string<W, I>(w: &mut W, i: I) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+    I: itoa::Integer,
+{
+    use itoa::Buffer;
+    let mut buf = Buffer::new();
+    w.write_str(buf.format(i))
+}
+
+#[inline]
+fn float_to_string<W, F>(w: &mut W, f: F) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+    F: ryu::Float,
+{
+    use ryu::Buffer;
+    let mut buf = Buffer::new();
+    w.write_str(buf.format(f))
+}
+
+pub(crate) fn escaped_str<W>(w: &mut W, value: &str) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+{
+    escaped_str_with(w, value, false)
+}
+
+/// Like `escaped_str`, also quoting ids with line breaks when
+/// `quote_line_breaks` is set.
+fn escaped_str_with<W>(w: &mut W, value: &str, quote_line_breaks: bool) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+{
+    let bytes = value.as_bytes();
+
+    if bytes.is_empty() {
+        return w.write_str("''");
+    }
+
+    let line_break = quote_line_breaks && bytes.iter().any(|b| matches!(b, b'\n' | b'\r'));
+    if !line_break
+        && !NOT_ID_START[bytes[0] as usize]
+        && !bytes[1..].iter().any(|b| NOT_ID[*b as usize])
+    {
+        return w.write_str(value);
+    }
+
+    w.write_char('\'')?;
+    let mut start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b != b'!' && b != b'\'' {
+            continue;
+        }
+
+        if start < i {
+            w.write_str(&value[start..i])?;
+        }
+        w.write_char('!')?;
+        w.write_char(b.into())?;
+
+        start = i + 1;
+    }
+    if start < bytes.len() {
+        w.write_str(&value[start..])?;
+    }
+    w.write_char('\'')
+}
+
+/// Reverses `escaped_str` for a key, returning `None` if the key is an id and
+/// so already unescaped.
+fn unescape_key(key: &str) -> Option<String> {
+    let quoted = key.strip_prefix('\'')?.strip_suffix('\'')?;
+    let mut raw = String::with_capacity(quoted.len());
+    let mut escape = false;
+    for c in quoted.chars() {
+        if c == '!' && !escape {
+            escape = true;
+        } else {
+            raw.push(c);
+            escape = false;
+        }
+    }
+    Some(raw)
+}
+
+fn encode_uri<W>(w: &mut W, value: &str) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+{
+    for &b in value.as_bytes() {
+        encode_uri_byte(w, b)?;
+    }
+    Ok(())
+}
+
+fn encode_uri_byte<W>(w: &mut W, b: u8) -> fmt::Result
+where
+    W: ?Sized + fmt::Write,
+{
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    match b {
+        b'-'
+        | b'A'..=b'Z'
+        | b'a'..=b'z'
+        | b'0'..=b'9'
+        | b'~'
+        | b'!'
+        | b'*'
+        | b'('
+        | b')'
+        | b'_'
+        | b'.'
+        | b'\''
+        | b','
+        | b':'
+        | b'@'
+        | b'$'
+        | b'/' => w.write_char(b.into()),
+        b' ' => w.write_char('+'),
+        _ => {
+            w.write_char('%')?;
+            w.write_char(HEX[(b >> 4) as usize].into())?;
+            w.write_char(HEX[(b & 0xf) as usize].into())
+        }
+    }
+}
+
+/// Percent-encodes what is written through it, for `Serializer::uri`.
+struct UriEncoder<'a>(&'a mut dyn fmt::Write);
+
+impl fmt::Write for UriEncoder<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        encode_uri(self.0, s)
+    }
+}
+
+const T: bool = true;
+const F: bool = false;
+// Lookup table: " '!:(),*@$" are true
+pub(crate) static NOT_ID: [bool; 256] = [
+    // 1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
+    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 0
+    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 1
+    T, T, F, F, T, F, F, T, T, T, T, F, T, F, F, F, // 2
+    F, F, F, F, F, F, F, F, F, F, T, F, F, F, F, F, // 3
+    T, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 4
+    F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 5
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 6
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 7
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 8
@@ -706,7 +1610,7 @@ static NOT_ID: [bool; 256] = [
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // f
 ];
 // Lookup table: "-0123456789 '!:(),*@$" are true
-static NOT_ID_START: [bool; 256] = [
+pub(crate) static NOT_ID_START: [bool; 256] = [
     // 1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 0
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // 1
@@ -726,13 +1630,227 @@ static NOT_ID_START: [bool; 256] = [
     F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, F, // f
 ];
 
+/// Serializes the value as rison into the given `fmt::Write`.
+pub fn to_fmt<W, T>(writer: W, value: &T) -> Result<()>
+where
+    W: fmt::Write,
+    T: ?Sized + Serialize,
+{
+    let mut ser = Serializer::new(writer);
+    value.serialize(&mut ser)
+}
+
+/// Serializes the value as rison into the given `io::Write`.
+///
+/// The output is written in many small pieces, so a `BufWriter` is
+/// recommended for files and sockets.
+#[cfg(feature = "std")]
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: ?Sized + Serialize,
+{
+    Serializer::from_io(writer).serialize_io(value)
+}
+
+/// Writes the values as rison lines, each value followed by a newline, for
+/// `de::StreamDeserializer` to read back.
+///
+/// Strings with line breaks are quoted even when they are ids, so that each
+/// value stays on its line. Each line is written with a single call to
+/// `write_all`.
+#[cfg(feature = "std")]
+pub fn to_writer_lines<W, I>(mut writer: W, values: I) -> Result<()>
+where
+    W: io::Write,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    let mut ser = Serializer::new(String::new());
+    ser.quote_line_breaks = true;
+    for value in values {
+        ser.writer.clear();
+        value.serialize(&mut ser)?;
+        ser.writer.push('\n');
+        writer
+            .write_all(ser.writer.as_bytes())
+            .map_err(|err| Error::new(ErrorCode::Io(err)))?;
+    }
+    Ok(())
+}
+
+/// Adapts an `io::Write` into a `fmt::Write`, keeping the io error that
+/// `fmt::Error` cannot carry. Created by `Serializer::from_io`.
+#[cfg(feature = "std")]
+pub struct IoWriter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W> IoWriter<W> {
+    pub fn new(inner: W) -> Self {
+        IoWriter { inner, error: None }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Takes the io error that made the last write fail, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: ?Sized + Serialize,
+{
+    to_string(value).map(String::into_bytes)
+}
+
 pub fn to_string<T>(value: &T) -> Result<String>
 where
     T: ?Sized + Serialize,
 {
-    let mut ser = Serializer {
-        buf: String::with_capacity(16),
-    };
+    let mut ser = Serializer::new(String::with_capacity(16));
+    value.serialize(&mut ser)?;
+    Ok(ser.writer)
+}
+
+/// Writes the value as rison through `Display`, without allocating.
+///
+/// ```
+/// let filters = vec!["a b", "c"];
+/// let url = format!("/search?q={}", serde_rison::display(&filters));
+/// assert_eq!(url, "/search?q=!('a b',c)");
+/// ```
+///
+/// Formatting fails with `fmt::Error` if the value cannot be serialized.
+pub fn display<T>(value: &T) -> Display<'_, T>
+where
+    T: ?Sized + Serialize,
+{
+    Display { value, uri: false }
+}
+
+/// Returned by [`display`].
+pub struct Display<'a, T: ?Sized> {
+    value: &'a T,
+    uri: bool,
+}
+
+impl<T: ?Sized> Display<'_, T> {
+    /// Writes the value as `to_string_uri` does, to put it in a URI as is.
+    pub fn uri(mut self) -> Self {
+        self.uri = true;
+        self
+    }
+}
+
+impl<T> fmt::Display for Display<'_, T>
+where
+    T: ?Sized + Serialize,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ser = Serializer::new(f);
+        ser.uri = self.uri;
+        self.value.serialize(&mut ser).map_err(|_| fmt::Error)
+    }
+}
+
+/// Fails the build of a `format_rison!` whose format string captures a
+/// variable inline, like `{q}` or `{:w$}`, since it could not be escaped.
+#[doc(hidden)]
+pub const fn check_no_inline_args(fmt: &str) {
+    const fn is_ident(b: u8) -> bool {
+        b == b'_' || b.is_ascii_alphanumeric() || b >= 0x80
+    }
+
+    let bytes = fmt.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'{' {
+            i += 1;
+            continue;
+        }
+        if i + 1 < bytes.len() && bytes[i + 1] == b'{' {
+            i += 2;
+            continue;
+        }
+        i += 1;
+        if i < bytes.len() && is_ident(bytes[i]) && !bytes[i].is_ascii_digit() {
+            panic!("format_rison! cannot escape inline arguments, pass them positionally");
+        }
+        // Widths and precisions can name an argument too, like `{:w$}`.
+        let mut word = i;
+        while i < bytes.len() && bytes[i] != b'}' {
+            if bytes[i] == b'$' && word < i && !bytes[word].is_ascii_digit() {
+                panic!("format_rison! cannot escape inline arguments, pass them positionally");
+            }
+            if !is_ident(bytes[i]) {
+                word = i + 1;
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Serializes a struct or map as O-Rison, the form of rison for a top-level
+/// object that leaves out the enclosing parentheses: `a:1,b:x`.
+///
+/// An empty map serializes to an empty string.
+pub fn to_string_orison<T>(value: &T) -> Result<String>
+where
+    T: ?Sized + Serialize,
+{
+    let mut ser = Serializer::new(String::with_capacity(16));
+    value.serialize(OrisonSerializer { ser: &mut ser })?;
+    Ok(ser.writer)
+}
+
+/// Serializes a sequence as A-Rison, the form of rison for a top-level array
+/// that leaves out the enclosing `!(` and `)`: `a,b,c`.
+///
+/// An empty sequence serializes to an empty string.
+pub fn to_string_arison<T>(value: &T) -> Result<String>
+where
+    T: ?Sized + Serialize,
+{
+    let mut ser = Serializer::new(String::with_capacity(16));
+    value.serialize(ArisonSerializer { ser: &mut ser })?;
+    Ok(ser.writer)
+}
+
+/// Serializes the value as rison that can be put in a URI without further
+/// escaping, like rison-node's `encode_uri`.
+///
+/// Only the characters that would be unsafe in a URI are percent-encoded,
+/// and spaces are written as `+`, so the structure stays readable.
+pub fn to_string_uri<T>(value: &T) -> Result<String>
+where
+    T: ?Sized + Serialize,
+{
+    let mut ser = Serializer::new(String::with_capacity(16)).uri();
     value.serialize(&mut ser)?;
-    Ok(ser.buf)
+    Ok(ser.writer)
 }
@@ -0,0 +1,242 @@
+//! The `Value` enum, a loosely typed way of representing any valid rison
+//! value.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, mem, str::FromStr};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+pub(crate) use self::de::MapKeyDeserializer;
+pub use self::index::Index;
+pub use self::ser::Serializer;
+pub use crate::map::Map;
+pub use crate::number::Number;
+
+mod de;
+mod from;
+mod index;
+mod ser;
+
+/// Represents any valid rison value.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum Value {
+    /// `!n`
+    #[default]
+    Null,
+    /// `!t` or `!f`
+    Bool(bool),
+    Number(Number),
+    String(String),
+    /// `!(...)`
+    Array(Vec<Value>),
+    /// `(...)`
+    Object(Map<String, Value>),
+}
+
+/// Prints the value as rison.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        crate::ser::to_fmt(f, self).map_err(|_| fmt::Error)
+    }
+}
+
+/// Parses a value from rison.
+impl FromStr for Value {
+    type Err = crate::de::Error;
+
+    fn from_str(s: &str) -> Result<Value, Self::Err> {
+        crate::de::from_str(s)
+    }
+}
+
+impl Value {
+    /// Index into an array or object. A string index can be used to access
+    /// a value in an object, and a usize index can be used to access an
+    /// element of an array.
+    ///
+    /// Returns `None` if the type of `self` does not match the type of the
+    /// index, or if the key or element does not exist.
+    pub fn get<I: Index>(&self, index: I) -> Option<&Value> {
+        index.index_into(self)
+    }
+
+    /// Mutably index into an array or object, like `get`.
+    pub fn get_mut<I: Index>(&mut self, index: I) -> Option<&mut Value> {
+        index.index_into_mut(self)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_null(&self) -> Option<()> {
+        match self {
+            Value::Null => Some(()),
+            _ => None,
+        }
+    }
+
+    pub fn is_boolean(&self) -> bool {
+        self.as_bool().is_some()
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_))
+    }
+
+    pub fn as_number(&self) -> Option<&Number> {
+        match self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the value is a number that can be represented by an
+    /// `i64`.
+    pub fn is_i64(&self) -> bool {
+        match self {
+            Value::Number(n) => n.is_i64(),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the value is a number that can be represented by a
+    /// `u64`.
+    pub fn is_u64(&self) -> bool {
+        match self {
+            Value::Number(n) => n.is_u64(),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the value is a number that is not an integer.
+    pub fn is_f64(&self) -> bool {
+        match self {
+            Value::Number(n) => n.is_f64(),
+            _ => false,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_number().and_then(Number::as_i64)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_number().and_then(Number::as_u64)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_number().and_then(Number::as_f64)
+    }
+
+    pub fn is_string(&self) -> bool {
+        self.as_str().is_some()
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.as_array().is_some()
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.as_object().is_some()
+    }
+
+    pub fn as_object(&self) -> Option<&Map<String, Value>> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_object_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Takes the value out, leaving `Null` in its place.
+    pub fn take(&mut self) -> Value {
+        mem::replace(self, Value::Null)
+    }
+
+    /// Drops the value one level at a time, from a list rather than
+    /// recursively. Dropping a value the usual way recurses as deep as it is
+    /// nested, so this is the way to get rid of values nested deeper than
+    /// the call stack allows, like those read with `unbounded_depth`.
+    ///
+    /// ```
+    /// # use serde_rison::Value;
+    /// let mut value = Value::Null;
+    /// for _ in 0..1_000_000 {
+    ///     value = Value::Array(vec![value]);
+    /// }
+    /// value.drop_deep();
+    /// ```
+    pub fn drop_deep(self) {
+        let mut stack = Vec::from([self]);
+        while let Some(mut value) = stack.pop() {
+            match &mut value {
+                Value::Array(vec) => stack.append(vec),
+                Value::Object(map) => stack.extend(mem::take(map).into_iter().map(|(_, v)| v)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Runs `f`, one level deeper in a `Value`. With `unbounded_depth` the stack
+/// grows on demand, so that `Value` can be serialized, converted and
+/// deserialized from however deep it is nested.
+fn nested<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "unbounded_depth")]
+    return crate::maybe_grow_stack(f);
+    #[cfg(not(feature = "unbounded_depth"))]
+    f()
+}
+
+/// Converts a `T` into a `Value`, following the same rules as
+/// `ser::to_string`.
+pub fn to_value<T>(value: T) -> Result<Value, crate::ser::Error>
+where
+    T: Serialize,
+{
+    nested(|| value.serialize(Serializer))
+}
+
+/// Interprets a `Value` as an instance of type `T`, without going through
+/// rison text.
+pub fn from_value<T>(value: Value) -> Result<T, crate::de::Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
@@ -0,0 +1,237 @@
+//! Feeds random and mutated input to the deserializer, which has to return
+//! an error rather than panic on anything, since rison usually comes from
+//! user-controlled query strings.
+
+use serde::{de::IgnoredAny, Deserialize};
+use serde_bytes::ByteBuf;
+use serde_rison::{
+    de::{from_reader, from_slice, from_str_arison, from_str_orison, from_url_encoded_str},
+    DuplicateKeys, Value,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    panic,
+};
+
+/// xorshift64*, so that failures reproduce from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const SEEDS: &[&str] = &[
+    "(a:0,b:foo,c:'23skidoo')",
+    "!(1,-2.5e3,!t,!f,!n,'',x)",
+    "(panels:!((gridData:(h:15,i:'1',w:24,x:0,y:0),id:'7a',type:visualization)),time:(from:now-15m,to:now))",
+    "'don!'t'",
+    "'it!!s'",
+    "(id:!n,type:/common/document)",
+    "(any:json,yes:!t)",
+    "!(!(1,2),(a:!()),())",
+    "340282366920938463463374607431768211455",
+    "-170141183460469231731687303715884105728",
+    "1.5e-400",
+    "(V:!(1,'x'))",
+    "(S:(a:1))",
+    "U",
+    "%28a%3A%21%28x%29%29",
+    "1e99999999999999999999999",
+    "-1e-99999999999999999999999",
+    "0.000000000000000000000000000000000000000000000000000000001e400",
+    "123456789012345678901234567890123456789012345678901234567890",
+    "!(!(!(!(!(!(!(!(!(!(!(!(!(!(!(!(1))))))))))))))))",
+    "(a:(a:(a:(a:(a:(a:(a:(a:(a:(a:(a:(a:(a:!n)))))))))))))",
+];
+
+/// Bytes that mean something to the parser, to make mutations likelier to
+/// get past the first few bytes.
+const TOKENS: &[&[u8]] = &[
+    b"(",
+    b")",
+    b"!(",
+    b"!",
+    b"'",
+    b"!'",
+    b"!!",
+    b":",
+    b",",
+    b"-",
+    b"e",
+    b"E",
+    b".",
+    b"0",
+    b"9",
+    b"!t",
+    b"!f",
+    b"!n",
+    b"%",
+    b"%2",
+    b"%28",
+    b"%ZZ",
+    b"\xff",
+    b"\xc3",
+    b"\xe2\x82",
+    b" ",
+];
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+enum Enum<'a> {
+    U,
+    V(u8, String),
+    N(Option<Box<Enum<'a>>>),
+    S {
+        a: i32,
+        #[serde(borrow)]
+        b: Option<Cow<'a, str>>,
+    },
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Struct<'a> {
+    a: Option<u64>,
+    b: Vec<f32>,
+    c: HashMap<i8, bool>,
+    d: Option<char>,
+    e: Option<ByteBuf>,
+    #[serde(borrow)]
+    f: Option<&'a str>,
+    g: Option<Enum<'a>>,
+    #[serde(flatten)]
+    rest: BTreeMap<String, Value>,
+}
+
+fn deserialize_all(input: &[u8]) {
+    let _ = from_slice::<Value>(input);
+    let _ = from_slice::<IgnoredAny>(input);
+    let _ = from_slice::<Struct>(input);
+    let _ = from_slice::<Enum>(input);
+    let _ = from_slice::<Vec<Option<(u128, i128)>>>(input);
+    let _ = from_slice::<BTreeMap<u16, f64>>(input);
+    let _ = from_reader::<_, Value>(input);
+    let mut de = serde_rison::de::Deserializer::from_slice(input).ignore_whitespace();
+    let _ = Struct::deserialize(&mut de).and_then(|_| de.end());
+    for policy in [DuplicateKeys::Error, DuplicateKeys::FirstWins] {
+        let mut de = serde_rison::de::Deserializer::from_slice(input).duplicate_keys(policy);
+        let _ = Struct::deserialize(&mut de);
+    }
+    if let Ok(s) = std::str::from_utf8(input) {
+        let _ = from_str_orison::<Value>(s);
+        let _ = from_str_arison::<Value>(s);
+        let _ = from_url_encoded_str::<Value>(s);
+        let _ = from_url_encoded_str::<Struct>(s);
+    }
+}
+
+/// Scales the number of runs, with `RISON_FUZZ_ITERATIONS=100` running a
+/// hundred times as many inputs as the default.
+fn iterations(default: usize) -> usize {
+    let scale = std::env::var("RISON_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1);
+    default * scale
+}
+
+fn check(input: &[u8]) {
+    if panic::catch_unwind(|| deserialize_all(input)).is_err() {
+        panic!(
+            "deserializer panicked on {:?}",
+            String::from_utf8_lossy(input)
+        );
+    }
+}
+
+fn mutate(rng: &mut Rng, input: &mut Vec<u8>) {
+    for _ in 0..=rng.below(4) {
+        let at = rng.below(input.len() + 1);
+        match rng.below(5) {
+            0 if at < input.len() => {
+                input.remove(at);
+            }
+            1 if at < input.len() => input[at] = rng.next() as u8,
+            2 => {
+                let end = (at + rng.below(8)).min(input.len());
+                input.drain(at..end);
+            }
+            3 => {
+                let token = rng.pick(TOKENS);
+                input.splice(at..at, token.iter().copied());
+            }
+            _ => {
+                let len = rng.below(8).min(input.len() - at);
+                let chunk = input[at..at + len].to_vec();
+                input.splice(at..at, chunk);
+            }
+        }
+    }
+}
+
+#[test]
+fn fuzz_random_bytes() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..iterations(5_000) {
+        let len = rng.below(32);
+        let input: Vec<u8> = (0..len)
+            .map(|_| {
+                if rng.below(2) == 0 {
+                    rng.next() as u8
+                } else {
+                    *rng.pick(b"()!',:-.e0123456789abtfn%'")
+                }
+            })
+            .collect();
+        check(&input);
+    }
+}
+
+#[test]
+fn fuzz_mutated_seeds() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..iterations(10_000) {
+        let mut input = rng.pick(SEEDS).as_bytes().to_vec();
+        mutate(&mut rng, &mut input);
+        check(&input);
+    }
+}
+
+#[test]
+fn fuzz_token_soup() {
+    let mut rng = Rng(0xdead_beef_cafe_f00d);
+    for _ in 0..iterations(5_000) {
+        let mut input = Vec::new();
+        for _ in 0..rng.below(24) {
+            let token = *rng.pick(TOKENS);
+            input.extend_from_slice(token);
+            if rng.below(3) == 0 {
+                input.extend_from_slice(rng.pick(SEEDS).as_bytes());
+            }
+        }
+        check(&input);
+    }
+}
+
+#[test]
+fn every_prefix() {
+    for seed in SEEDS {
+        for end in 0..=seed.len() {
+            check(&seed.as_bytes()[..end]);
+        }
+    }
+}
@@ -1,14 +1,43 @@
 use maplit::{btreemap, hashmap};
 use ordered_float::OrderedFloat;
-use serde::{ser, Serialize};
+use serde::{
+    de::{DeserializeOwned, IntoDeserializer},
+    ser, Deserialize, Serialize,
+};
 use serde_bytes::{ByteBuf, Bytes};
-use serde_rison::{de::from_str, ser::to_string};
-use std::collections::HashMap;
+use serde_rison::{
+    de::{
+        from_reader, from_slice, from_str, from_str_arison, from_str_orison, from_url_encoded_str,
+        Deserializer, Error, LimitKind,
+    },
+    display, format_rison, from_value,
+    read::{PercentDecodeRead, Read},
+    rison,
+    ser::{
+        to_fmt, to_string, to_string_arison, to_string_orison, to_string_uri, to_vec, to_writer,
+    },
+    to_value, Map, Number, Value,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    io,
+};
 
 fn ok<T: ?Sized + ser::Serialize>(value: &T) -> String {
     to_string(value).unwrap()
 }
 
+fn round_trip<T: ser::Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+    let s = ok(&value);
+    assert_eq!(from_str::<T>(&s).unwrap(), value, "{}", s);
+}
+
+fn de_err<T: DeserializeOwned + Debug>(s: &str) -> String {
+    from_str::<T>(s).unwrap_err().to_string()
+}
+
 #[test]
 fn test_ser_unit() {
     assert_eq!(ok(&()), "!n");
@@ -23,9 +52,9 @@ fn test_ser_bool() {
 #[test]
 fn test_de_bool() {
     let b: bool = from_str("!t").unwrap();
-    assert_eq!(b, true);
+    assert!(b);
     let b: bool = from_str("!f").unwrap();
-    assert_eq!(b, false);
+    assert!(!b);
 
     assert_eq!(
         from_str::<bool>("!n").unwrap_err().to_string(),
@@ -211,6 +240,215 @@ fn test_ser_map() {
     );
 }
 
+#[test]
+fn test_ser_uri() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct S {
+        any: String,
+        yes: bool,
+        list: Vec<f64>,
+        map: BTreeMap<String, Option<String>>,
+    }
+
+    assert_eq!(to_string_uri("hello world").unwrap(), "'hello+world'");
+    assert_eq!(to_string_uri("a&b=c#d%").unwrap(), "a%26b%3Dc%23d%25");
+    assert_eq!(to_string_uri("a+b").unwrap(), "a%2Bb");
+    assert_eq!(to_string_uri("é").unwrap(), "%C3%A9");
+    assert_eq!(to_string_uri("it's!").unwrap(), "'it!'s!!'");
+    assert_eq!(to_string_uri("~*_./@$").unwrap(), "'~*_./@$'");
+    assert_eq!(to_string_uri(&-1.5e-7).unwrap(), "-1.5e-7");
+    assert_eq!(
+        to_string_uri(&btreemap! {"a b" => vec!["x/y"], "c" => vec![]}).unwrap(),
+        "('a+b':!(x/y),c:!())"
+    );
+
+    let value = S {
+        any: "json & rison".to_owned(),
+        yes: true,
+        list: vec![1.0, -2.5],
+        map: btreemap! {
+            "100%".to_owned() => Some("日本".to_owned()),
+            "k".to_owned() => None,
+        },
+    };
+    let s = to_string_uri(&value).unwrap();
+    assert_eq!(
+        s,
+        "(any:'json+%26+rison',list:!(1.0,-2.5),map:('100%25':%E6%97%A5%E6%9C%AC,k:!n),yes:!t)"
+    );
+    assert_eq!(from_url_encoded_str::<S>(&s).unwrap(), value);
+
+    // The same output from a configured serializer, written anywhere.
+    let mut ser = serde_rison::ser::Serializer::new(String::from("q="))
+        .uri()
+        .key_order(serde_rison::ser::KeyOrder::Insertion);
+    value.serialize(&mut ser).unwrap();
+    assert_eq!(
+        ser.into_inner(),
+        "q=(any:'json+%26+rison',yes:!t,list:!(1.0,-2.5),map:('100%25':%E6%97%A5%E6%9C%AC,k:!n))"
+    );
+    let mut buf = Vec::new();
+    serde_rison::ser::Serializer::from_io(&mut buf)
+        .uri()
+        .serialize_io(&value)
+        .unwrap();
+    assert_eq!(buf, s.as_bytes());
+    assert_eq!(
+        format!("/search?q={}", display(&value).uri()),
+        format!("/search?q={}", s)
+    );
+}
+
+#[test]
+fn test_orison() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct S {
+        a: u32,
+        b: String,
+        c: Vec<Option<bool>>,
+        d: BTreeMap<String, i32>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Wrapper(BTreeMap<String, String>);
+
+    let value = S {
+        a: 1,
+        b: "x y".to_owned(),
+        c: vec![Some(true), None],
+        d: btreemap! {"k".to_owned() => -1},
+    };
+    let s = to_string_orison(&value).unwrap();
+    assert_eq!(s, "a:1,b:'x y',c:!(!t,!n),d:(k:-1)");
+    assert_eq!(from_str_orison::<S>(&s).unwrap(), value);
+
+    assert_eq!(
+        to_string_orison(&BTreeMap::<String, u8>::new()).unwrap(),
+        ""
+    );
+    assert_eq!(
+        from_str_orison::<HashMap<String, u8>>("").unwrap(),
+        HashMap::new()
+    );
+    let value = Wrapper(btreemap! {"any".to_owned() => "json".to_owned()});
+    assert_eq!(to_string_orison(&value).unwrap(), "any:json");
+    assert_eq!(from_str_orison::<Wrapper>("any:json").unwrap(), value);
+
+    assert_eq!(
+        to_string_orison(&1).unwrap_err().to_string(),
+        "O-Rison value must be a struct or map"
+    );
+    assert_eq!(
+        to_string_orison(&vec![1]).unwrap_err().to_string(),
+        "O-Rison value must be a struct or map"
+    );
+    assert_eq!(
+        from_str_orison::<Vec<u8>>("a:1").unwrap_err().to_string(),
+        "O-Rison value must be a struct or map at position 0"
+    );
+    assert_eq!(
+        from_str_orison::<HashMap<String, u8>>("a:1,")
+            .unwrap_err()
+            .to_string(),
+        "trailing comma at position 4"
+    );
+    assert_eq!(
+        from_str_orison::<HashMap<String, u8>>("a:1)")
+            .unwrap_err()
+            .to_string(),
+        "trailing characters at position 4"
+    );
+    assert_eq!(
+        from_str_orison::<HashMap<String, u8>>("(a:1)")
+            .unwrap_err()
+            .to_string(),
+        "invalid type: map, expected a string at position 1"
+    );
+    assert_eq!(
+        from_str_orison::<S>("a:1,b:x").unwrap_err().to_string(),
+        "missing field `c` at position 7"
+    );
+}
+
+#[test]
+fn test_arison() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Point(i32, i32);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Tags(Vec<String>);
+
+    let value = vec!["a".to_owned(), "b c".to_owned(), "".to_owned()];
+    let s = to_string_arison(&value).unwrap();
+    assert_eq!(s, "a,'b c',''");
+    assert_eq!(from_str_arison::<Vec<String>>(&s).unwrap(), value);
+
+    assert_eq!(to_string_arison(&Vec::<u8>::new()).unwrap(), "");
+    assert_eq!(from_str_arison::<Vec<u8>>("").unwrap(), Vec::<u8>::new());
+    assert_eq!(
+        from_str_arison::<Vec<Option<u8>>>("''")
+            .unwrap_err()
+            .to_string(),
+        "[0]: invalid type: string \"\", expected u8 at position 2"
+    );
+    assert_eq!(from_str_arison::<Vec<String>>("''").unwrap(), vec![""]);
+
+    assert_eq!(to_string_arison(&Point(1, -2)).unwrap(), "1,-2");
+    assert_eq!(from_str_arison::<Point>("1,-2").unwrap(), Point(1, -2));
+    assert_eq!(to_string_arison(&(1, "x", true)).unwrap(), "1,x,!t");
+    assert_eq!(
+        from_str_arison::<(u8, String, bool)>("1,x,!t").unwrap(),
+        (1, "x".to_owned(), true)
+    );
+    let value = Tags(vec!["x".to_owned(), "y".to_owned()]);
+    assert_eq!(to_string_arison(&value).unwrap(), "x,y");
+    assert_eq!(from_str_arison::<Tags>("x,y").unwrap(), value);
+    assert_eq!(to_string_arison(&Bytes::new(b"\x01\x02")).unwrap(), "1,2");
+    assert_eq!(
+        from_str_arison::<ByteBuf>("1,2").unwrap(),
+        ByteBuf::from(vec![1, 2])
+    );
+    assert_eq!(
+        from_str_arison::<Vec<Vec<u8>>>("!(1),!()").unwrap(),
+        vec![vec![1], vec![]]
+    );
+
+    assert_eq!(
+        to_string_arison(&"abc").unwrap_err().to_string(),
+        "A-Rison value must be a sequence"
+    );
+    assert_eq!(
+        to_string_arison(&btreemap! {"a" => 1})
+            .unwrap_err()
+            .to_string(),
+        "A-Rison value must be a sequence"
+    );
+    assert_eq!(
+        from_str_arison::<HashMap<String, u8>>("a:1")
+            .unwrap_err()
+            .to_string(),
+        "A-Rison value must be a sequence at position 0"
+    );
+    assert_eq!(
+        from_str_arison::<String>("a").unwrap_err().to_string(),
+        "A-Rison value must be a sequence at position 0"
+    );
+    assert_eq!(
+        from_str_arison::<Vec<u8>>("1,2,").unwrap_err().to_string(),
+        "trailing comma at position 4"
+    );
+    assert_eq!(
+        from_str_arison::<Vec<u8>>("1,2)").unwrap_err().to_string(),
+        "trailing characters at position 4"
+    );
+    assert_eq!(
+        from_str_arison::<(u8, u8)>("1,2,3")
+            .unwrap_err()
+            .to_string(),
+        "trailing characters at position 4"
+    );
+}
+
 #[test]
 fn test_ser_map_err() {
     assert!(to_string(&hashmap! {
@@ -445,8 +683,8 @@ fn test_key_error() {
         S { a: 0 } => 1,
     })
     .expect_err("Not Error");
-    assert_eq!(err.to_string(), "key must be a string");
-    assert_eq!(format!("{:?}", err), "key must be a string");
+    assert_eq!(err.to_string(), "key must be a string, found struct");
+    assert_eq!(format!("{:?}", err), "key must be a string, found struct");
 }
 
 #[test]
@@ -466,3 +704,1884 @@ fn test_custom_error() {
     assert_eq!(err.to_string(), "foo!");
     assert_eq!(format!("{:?}", err), "foo!");
 }
+
+#[test]
+fn test_de_unit() {
+    from_str::<()>("!n").unwrap();
+    assert_eq!(
+        de_err::<()>("!t"),
+        "invalid type: boolean `true`, expected unit at position 2"
+    );
+    assert_eq!(de_err::<()>(""), "EOF while parsing a value at position 0");
+}
+
+#[test]
+fn test_de_integer() {
+    assert_eq!(from_str::<i32>("0").unwrap(), 0);
+    assert_eq!(from_str::<i32>("-12").unwrap(), -12);
+    assert_eq!(from_str::<u8>("255").unwrap(), 255);
+    round_trip(i8::MIN);
+    round_trip(i8::MAX);
+    round_trip(i16::MIN);
+    round_trip(i16::MAX);
+    round_trip(i32::MIN);
+    round_trip(i32::MAX);
+    round_trip(i64::MIN);
+    round_trip(i64::MAX);
+    round_trip(u64::MAX);
+
+    assert_eq!(
+        de_err::<u8>("256"),
+        "invalid value: integer `256`, expected u8 at position 3"
+    );
+    assert_eq!(
+        de_err::<u32>("-1"),
+        "invalid value: integer `-1`, expected u32 at position 2"
+    );
+    assert_eq!(
+        de_err::<i32>("a"),
+        "invalid type: string \"a\", expected i32 at position 1"
+    );
+    assert_eq!(de_err::<i32>("01"), "invalid number at position 2");
+    assert_eq!(
+        de_err::<i32>("-"),
+        "EOF while parsing a value at position 1"
+    );
+}
+
+#[test]
+fn test_de_integer_exact() {
+    assert_eq!(
+        from_str::<u64>("9007199254740993").unwrap(),
+        9007199254740993
+    );
+    assert_eq!(
+        from_str::<i64>("-9007199254740993").unwrap(),
+        -9007199254740993
+    );
+    assert_eq!(from_str::<u64>("18446744073709551615").unwrap(), u64::MAX);
+    assert_eq!(from_str::<i64>("-9223372036854775808").unwrap(), i64::MIN);
+    assert_eq!(from_str::<i32>("-0").unwrap(), 0);
+    round_trip(u128::MIN);
+    round_trip(u128::MAX);
+    round_trip(i128::MIN);
+    round_trip(i128::MAX);
+    round_trip(u64::MAX as u128 + 1);
+    round_trip(i64::MIN as i128 - 1);
+    round_trip(-1_i128);
+    round_trip(hashmap! {
+        u128::MAX => i128::MIN,
+    });
+
+    assert_eq!(
+        de_err::<i32>("1.0"),
+        "invalid type: floating point `1.0`, expected i32 at position 3"
+    );
+    assert_eq!(
+        de_err::<u64>("18446744073709551616"),
+        "number out of range at position 20"
+    );
+    assert_eq!(
+        de_err::<i64>("-9223372036854775809"),
+        "number out of range at position 20"
+    );
+    assert_eq!(
+        de_err::<Vec<u64>>("!(1,99999999999999999999999)"),
+        "[1]: number out of range at position 24"
+    );
+    assert_eq!(
+        de_err::<u128>("340282366920938463463374607431768211456"),
+        "number out of range at position 39"
+    );
+    assert_eq!(
+        de_err::<i128>("-170141183460469231731687303715884105729"),
+        "number out of range at position 40"
+    );
+    assert_eq!(
+        de_err::<i128>("170141183460469231731687303715884105728"),
+        "invalid value: u128, expected i128 at position 39"
+    );
+    assert_eq!(
+        de_err::<u128>("-1"),
+        "invalid value: integer `-1`, expected u128 at position 2"
+    );
+    assert_eq!(
+        de_err::<u128>("1.5"),
+        "invalid type: floating point `1.5`, expected u128 at position 3"
+    );
+
+    // Without a type to go by, integers are not approximated by floats
+    // either: 128-bit ones reach the visitor as such and longer ones are
+    // out of range. A `Value` holds 64-bit integers only.
+    assert_eq!(
+        de_err::<Value>("18446744073709551616"),
+        "number out of range at position 20"
+    );
+    assert_eq!(
+        de_err::<Value>("!(1,-9223372036854775809)"),
+        "[1]: number out of range at position 24"
+    );
+    assert_eq!(
+        de_err::<Value>(&format!("1{}", "0".repeat(40))),
+        "number out of range at position 41"
+    );
+    assert_eq!(
+        from_str::<Value>("1e40").unwrap(),
+        Value::Number(Number::from_f64(1e40).unwrap())
+    );
+    assert_eq!(from_str::<Value>("-0").unwrap().as_f64(), Some(-0.0));
+    assert_eq!(from_str::<Value>("-0").unwrap().to_string(), "-0.0");
+    assert_eq!(
+        from_str::<Number>("-9223372036854775808").unwrap(),
+        Number::from(i64::MIN)
+    );
+    assert_eq!(
+        de_err::<Number>("18446744073709551616"),
+        "number out of range at position 20"
+    );
+}
+
+#[test]
+fn test_de_float() {
+    assert_eq!(from_str::<f64>("1.5").unwrap(), 1.5);
+    assert_eq!(from_str::<f64>("-0.25").unwrap(), -0.25);
+    assert_eq!(from_str::<f64>("3").unwrap(), 3.0);
+    assert_eq!(from_str::<f64>("1e3").unwrap(), 1000.0);
+    assert_eq!(from_str::<f64>("1e-3").unwrap(), 0.001);
+    assert_eq!(from_str::<f32>("1.0").unwrap(), 1.0);
+    round_trip(0.0_f64);
+    round_trip(1.0_f64);
+    round_trip(-2.5_f64);
+
+    assert_eq!(
+        de_err::<f64>("1."),
+        "EOF while parsing a value at position 2"
+    );
+    assert_eq!(de_err::<f64>("1.e1"), "invalid number at position 3");
+    assert_eq!(
+        de_err::<f64>("1e"),
+        "EOF while parsing a value at position 2"
+    );
+    assert_eq!(de_err::<f64>("1e999"), "number out of range at position 5");
+}
+
+#[test]
+fn test_de_float_exact() {
+    assert_eq!(from_str::<f64>("1e-7").unwrap(), 1e-7);
+    assert_eq!(from_str::<f64>("2.5e+30").unwrap(), 2.5e30);
+    assert_eq!(from_str::<f64>("0.1").unwrap(), 0.1);
+    assert_eq!(from_str::<f64>("0.30000000000000004").unwrap(), 0.1 + 0.2);
+    assert_eq!(
+        from_str::<f64>("9007199254740993").unwrap(),
+        9007199254740992.0
+    );
+    assert_eq!(
+        from_str::<f64>("123456789012345678901234567890").unwrap(),
+        123456789012345678901234567890.0
+    );
+    assert_eq!(
+        from_str::<f64>("2.2250738585072011e-308").unwrap(),
+        2.225073858507201e-308
+    );
+    assert_eq!(from_str::<f64>("5e-324").unwrap(), 5e-324);
+    assert_eq!(from_str::<f64>("1e-400").unwrap(), 0.0);
+    assert!(from_str::<f64>("-0").unwrap().is_sign_negative());
+    assert!(from_str::<f64>("-0.0").unwrap().is_sign_negative());
+    assert_eq!(from_str::<f32>("16777217").unwrap(), 16777216.0);
+    // Rounding through f64 first would land on a tie and give 1.0.
+    assert_eq!(
+        from_str::<f32>("1.00000005960464477539062500001").unwrap(),
+        1.000_000_1
+    );
+
+    for v in [
+        f64::MAX,
+        f64::MIN,
+        f64::MIN_POSITIVE,
+        f64::EPSILON,
+        -5e-324,
+        1.7976931348623155e308,
+    ] {
+        round_trip(v);
+    }
+    for v in [f32::MAX, f32::MIN, f32::MIN_POSITIVE, f32::EPSILON, 1e-45] {
+        round_trip(v);
+    }
+
+    // xorshift64, so every run checks the same bit patterns.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..20_000 {
+        let f = f64::from_bits(next());
+        if f.is_finite() {
+            assert_eq!(from_str::<f64>(&ok(&f)).unwrap().to_bits(), f.to_bits());
+        }
+        let f = f32::from_bits(next() as u32);
+        if f.is_finite() {
+            assert_eq!(from_str::<f32>(&ok(&f)).unwrap().to_bits(), f.to_bits());
+        }
+    }
+
+    assert_eq!(de_err::<f64>("1e309"), "number out of range at position 5");
+    assert_eq!(de_err::<f32>("1e39"), "number out of range at position 4");
+    assert_eq!(
+        de_err::<f64>("1e+"),
+        "EOF while parsing a value at position 3"
+    );
+    assert_eq!(de_err::<f64>("1e+a"), "invalid number at position 4");
+}
+
+#[test]
+fn test_de_str() {
+    assert_eq!(from_str::<String>("a").unwrap(), "a");
+    assert_eq!(from_str::<String>("''").unwrap(), "");
+    assert_eq!(from_str::<char>("a").unwrap(), 'a');
+    round_trip(String::from("abc"));
+    round_trip(String::from("1"));
+    round_trip(String::from("あ"));
+    round_trip(String::from("I'm not a JSON!"));
+    round_trip(String::from("a-b.c_d~"));
+    round_trip(String::from(" "));
+    round_trip('!');
+    round_trip('\'');
+
+    assert_eq!(
+        de_err::<String>("'abc"),
+        "EOF while parsing a string at position 4"
+    );
+    assert_eq!(
+        de_err::<String>("'a!b'"),
+        "invalid escape char: 98 at position 4"
+    );
+    assert_eq!(
+        de_err::<String>("1"),
+        "invalid type: integer `1`, expected a string at position 1"
+    );
+    assert_eq!(
+        de_err::<String>("!t"),
+        "invalid type: boolean `true`, expected a string at position 2"
+    );
+    assert_eq!(de_err::<String>(")"), "expected value at position 1");
+}
+
+#[test]
+fn test_de_borrowed_str() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct S<'a> {
+        a: &'a str,
+        #[serde(borrow)]
+        b: Cow<'a, str>,
+        #[serde(with = "serde_bytes")]
+        c: &'a [u8],
+    }
+
+    let input = String::from("(a:abc,b:'x y',c:'bytes')");
+    let s: S = from_str(&input).unwrap();
+    assert_eq!(s.a, "abc");
+    assert!(matches!(s.b, Cow::Borrowed("x y")));
+    assert_eq!(s.c, b"bytes");
+
+    let s: S = from_str("(a:'',b:'I!'m',c:c)").unwrap();
+    assert_eq!(s.a, "");
+    assert!(matches!(s.b, Cow::Owned(ref b) if b == "I'm"));
+
+    assert_eq!(
+        from_str::<&str>("'a!!b'").unwrap_err().to_string(),
+        "invalid type: string \"a!b\", expected a borrowed string at position 6"
+    );
+    assert_eq!(
+        from_str::<Vec<&str>>("!(a,'b c',あ)").unwrap(),
+        vec!["a", "b c", "あ"]
+    );
+    assert_eq!(
+        from_slice::<String>(b"'\xff'").unwrap_err().to_string(),
+        "invalid unicode in string at position 3"
+    );
+}
+
+#[test]
+fn test_de_reader() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct S {
+        a: Vec<i64>,
+        b: String,
+        c: Option<bool>,
+        d: f64,
+    }
+
+    let input = "(a:!(1,-2,3),b:'it!'s',c:!n,d:1.5e3)";
+    assert_eq!(
+        from_reader::<_, S>(input.as_bytes()).unwrap(),
+        S {
+            a: vec![1, -2, 3],
+            b: "it's".to_owned(),
+            c: None,
+            d: 1500.0,
+        }
+    );
+    assert_eq!(from_reader::<_, String>(&b"abc"[..]).unwrap(), "abc");
+    assert_eq!(
+        from_reader::<_, Option<String>>(&b"'!!n'"[..]).unwrap(),
+        Some("!n".to_owned())
+    );
+    assert_eq!(
+        from_reader::<_, String>(&b"'a!xb'"[..])
+            .unwrap_err()
+            .to_string(),
+        "invalid escape char: 120 at position 4"
+    );
+    assert_eq!(
+        from_reader::<_, Vec<u8>>(&b"!(1,2"[..])
+            .unwrap_err()
+            .to_string(),
+        "EOF while parsing a list at position 5"
+    );
+
+    // Files end with a line break.
+    let map: BTreeMap<String, u8> = from_reader(&b"(a:1)\n"[..]).unwrap();
+    assert_eq!(map, BTreeMap::from([("a".to_owned(), 1)]));
+    assert_eq!(from_reader::<_, String>(&b"abc\r\n"[..]).unwrap(), "abc");
+    assert_eq!(
+        from_reader::<_, String>(&b"abc\ndef"[..])
+            .unwrap_err()
+            .to_string(),
+        "trailing characters at position 5"
+    );
+    assert_eq!(
+        from_str::<String>("abc\n").unwrap(),
+        "abc\n",
+        "other sources are unchanged"
+    );
+
+    struct Failing(usize);
+
+    impl io::Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("broken pipe"));
+            }
+            self.0 -= 1;
+            buf[0] = b'1';
+            Ok(1)
+        }
+    }
+
+    let err = from_reader::<_, u64>(Failing(3)).unwrap_err();
+    assert_eq!(err.to_string(), "broken pipe at position 3");
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn test_de_url_encoded() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct S<'a> {
+        a: u32,
+        b: String,
+        c: &'a str,
+        d: Vec<bool>,
+    }
+
+    assert_eq!(
+        from_url_encoded_str::<S>("(a:1,b:'hello+world',c:raw,d:!(!t,!f))").unwrap(),
+        S {
+            a: 1,
+            b: "hello world".to_owned(),
+            c: "raw",
+            d: vec![true, false],
+        }
+    );
+    assert_eq!(
+        from_url_encoded_str::<S>("%28a%3A1%2Cb%3A%27it%21%27s%27%2Cc%3Ax%2Cd%3A%21%28%21t%29%29")
+            .unwrap(),
+        S {
+            a: 1,
+            b: "it's".to_owned(),
+            c: "x",
+            d: vec![true],
+        }
+    );
+    assert_eq!(from_url_encoded_str::<String>("%E3%81%82").unwrap(), "あ");
+    assert_eq!(from_url_encoded_str::<String>("a%2Bb").unwrap(), "a+b");
+    assert_eq!(from_url_encoded_str::<String>("'a%20b'").unwrap(), "a b");
+    assert_eq!(from_url_encoded_str::<f64>("-1.5e%2B2").unwrap(), -150.0);
+    assert_eq!(from_url_encoded_str::<Option<()>>("%21n").unwrap(), None);
+
+    let mut de = Deserializer::new(PercentDecodeRead::new(b"'a+b%21%21'"));
+    assert_eq!(String::deserialize(&mut de).unwrap(), "a+b!");
+
+    // Positions are offsets in the encoded input.
+    assert_eq!(
+        from_url_encoded_str::<Vec<u8>>("!(1,%2")
+            .unwrap_err()
+            .to_string(),
+        "invalid percent-encoding at position 5"
+    );
+    assert_eq!(
+        from_url_encoded_str::<String>("'ab%zz'")
+            .unwrap_err()
+            .to_string(),
+        "invalid percent-encoding at position 4"
+    );
+    assert_eq!(
+        from_url_encoded_str::<HashMap<String, bool>>("(a%3A%21x)")
+            .unwrap_err()
+            .to_string(),
+        "a: invalid escape char: 120 at position 9"
+    );
+    assert_eq!(
+        from_url_encoded_str::<&str>("'a%21%21b'")
+            .unwrap_err()
+            .to_string(),
+        "invalid type: string \"a!b\", expected a borrowed string at position 10"
+    );
+}
+
+#[test]
+fn test_de_custom_read() {
+    // Only the byte level methods are implemented, strings go through the
+    // default copying implementations.
+    struct Chunks<'a> {
+        chunks: Vec<&'a [u8]>,
+        position: usize,
+    }
+
+    impl<'a> Chunks<'a> {
+        fn byte(&self, n: usize) -> Option<u8> {
+            self.chunks.iter().flat_map(|c| c.iter()).nth(n).copied()
+        }
+    }
+
+    impl<'de, 'a> Read<'de> for Chunks<'a> {
+        fn next(&mut self) -> Result<Option<u8>, Error> {
+            let b = self.byte(0);
+            if b.is_some() {
+                self.eat_char();
+            }
+            Ok(b)
+        }
+
+        fn peek(&mut self) -> Result<Option<u8>, Error> {
+            Ok(self.byte(0))
+        }
+
+        fn peek_second(&mut self) -> Result<Option<u8>, Error> {
+            Ok(self.byte(1))
+        }
+
+        fn position(&self) -> usize {
+            self.position
+        }
+
+        fn eat_char(&mut self) {
+            self.chunks[0] = &self.chunks[0][1..];
+            if self.chunks[0].is_empty() {
+                self.chunks.remove(0);
+            }
+            self.position += 1;
+        }
+    }
+
+    let read = Chunks {
+        chunks: vec![b"(a:'x", b" !'y!'',b", b":!(!t,!", b"n))"],
+        position: 0,
+    };
+    let mut de = Deserializer::new(read);
+    assert_eq!(
+        HashMap::<String, Vec<Option<bool>>>::deserialize(&mut de)
+            .unwrap_err()
+            .to_string(),
+        "a: invalid type: string \"x 'y'\", expected a sequence at position 12"
+    );
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Value {
+        Str(String),
+        Seq(Vec<Option<bool>>),
+    }
+
+    let read = Chunks {
+        chunks: vec![b"(a:'x", b" !'y!'',b", b":!(!t,!", b"n))"],
+        position: 0,
+    };
+    let mut de = Deserializer::new(read);
+    assert_eq!(
+        HashMap::<String, Value>::deserialize(&mut de).unwrap(),
+        hashmap! {
+            "a".to_owned() => Value::Str("x 'y'".to_owned()),
+            "b".to_owned() => Value::Seq(vec![Some(true), None]),
+        }
+    );
+
+    // Sources that override the string methods keep them, with the limits
+    // checked on what they return.
+    struct Shouting<'a>(serde_rison::read::SliceRead<'a>);
+
+    impl<'de> Read<'de> for Shouting<'de> {
+        fn next(&mut self) -> Result<Option<u8>, Error> {
+            self.0.next()
+        }
+
+        fn peek(&mut self) -> Result<Option<u8>, Error> {
+            self.0.peek()
+        }
+
+        fn peek_second(&mut self) -> Result<Option<u8>, Error> {
+            self.0.peek_second()
+        }
+
+        fn position(&self) -> usize {
+            self.0.position()
+        }
+
+        fn eat_char(&mut self) {
+            self.0.eat_char()
+        }
+
+        fn parse_str<'s>(
+            &'s mut self,
+            scratch: &'s mut Vec<u8>,
+        ) -> Result<serde_rison::read::Reference<'de, 's, str>, Error> {
+            let s = self.0.parse_str(scratch)?.to_uppercase();
+            *scratch = s.into_bytes();
+            Ok(serde_rison::read::Reference::Copied(
+                std::str::from_utf8(scratch).unwrap(),
+            ))
+        }
+    }
+
+    let shouting = |s| Shouting(serde_rison::read::SliceRead::new(s));
+    let mut de = Deserializer::new(shouting(b"!(ab,'c d')"));
+    assert_eq!(Vec::<String>::deserialize(&mut de).unwrap(), ["AB", "C D"]);
+    let mut de = Deserializer::new(shouting(b"!(ab,'c d')")).max_string_len(2);
+    assert_eq!(
+        Vec::<String>::deserialize(&mut de).unwrap_err().to_string(),
+        "[1]: string too long at position 6"
+    );
+}
+
+#[test]
+fn test_de_bytes() {
+    assert_eq!(from_str::<ByteBuf>("!()").unwrap(), ByteBuf::new());
+    assert_eq!(
+        from_str::<ByteBuf>("!(1,2,3)").unwrap(),
+        ByteBuf::from(vec![1, 2, 3])
+    );
+    assert_eq!(
+        from_str::<ByteBuf>("abc").unwrap(),
+        ByteBuf::from(b"abc".to_vec())
+    );
+    round_trip(ByteBuf::from(vec![0, 255]));
+}
+
+#[test]
+fn test_de_option() {
+    round_trip(Some(1));
+    round_trip(Option::<i32>::None);
+    round_trip(Some(true));
+    round_trip(vec![Some(vec![1]), None]);
+    assert_eq!(from_str::<Option<bool>>("!f").unwrap(), Some(false));
+}
+
+#[test]
+fn test_de_seq() {
+    round_trip(Vec::<i32>::new());
+    round_trip(vec![1]);
+    round_trip(vec![1, 2]);
+    round_trip(vec![vec![], vec![String::from("a")]]);
+    round_trip((1,));
+    round_trip((String::from("a"), 1));
+    round_trip(((3,), (2,), 1));
+
+    assert_eq!(de_err::<Vec<i32>>("!(1,)"), "trailing comma at position 5");
+    assert_eq!(
+        de_err::<Vec<i32>>("!(1"),
+        "EOF while parsing a list at position 3"
+    );
+    assert_eq!(
+        de_err::<Vec<i32>>("!(1:"),
+        "expected `,` or `)` at position 4"
+    );
+    assert_eq!(
+        de_err::<(i32,)>("!(1,2)"),
+        "trailing characters at position 4"
+    );
+    assert_eq!(
+        de_err::<Vec<i32>>("(a:1)"),
+        "invalid type: map, expected a sequence at position 1"
+    );
+}
+
+#[test]
+fn test_de_map() {
+    round_trip(HashMap::<String, String>::new());
+    round_trip(hashmap! {
+        String::from("I'm a key!") => String::from("I'm a value!"),
+    });
+    round_trip(hashmap! {
+        '!' => 1,
+        '\'' => 2,
+        'a' => 3,
+    });
+    round_trip(hashmap! {
+        1_u64 => 10_u128,
+        2_u64 => 20_u128,
+    });
+    round_trip(hashmap! {
+        i64::MIN => 1,
+    });
+    round_trip(hashmap! {
+        String::from("key1") => hashmap! {
+            1 => vec![hashmap! { String::from("a") => String::from("A") }],
+        },
+        String::from("key2") => hashmap! {},
+    });
+    assert_eq!(
+        from_str::<HashMap<String, i32>>("(1:2,-1:3)").unwrap(),
+        hashmap! {
+            String::from("1") => 2,
+            String::from("-1") => 3,
+        }
+    );
+
+    assert_eq!(
+        de_err::<HashMap<String, i32>>("(a:1,)"),
+        "trailing comma at position 6"
+    );
+    assert_eq!(
+        de_err::<HashMap<String, i32>>("(a,1)"),
+        "invalid char: 44, expected 58 at position 3"
+    );
+    assert_eq!(
+        de_err::<HashMap<String, i32>>("(a:1"),
+        "EOF while parsing an object at position 4"
+    );
+}
+
+#[test]
+fn test_de_struct() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Unit;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct N(u8);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Abc(u8, u16, u32);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct S {
+        #[serde(rename = "a!")]
+        a: u32,
+        b: Option<String>,
+        c: Vec<N>,
+    }
+
+    round_trip(Unit);
+    round_trip(N(1));
+    round_trip(Abc(2, 1, 0));
+    round_trip(S {
+        a: 1,
+        b: Some(String::from("x y")),
+        c: vec![N(1), N(2)],
+    });
+    round_trip(vec![S {
+        a: 1,
+        b: None,
+        c: vec![],
+    }]);
+    assert_eq!(
+        from_str::<S>("(b:!n,ignored:!((x:!(1,'2',!t)),-1.5e3),c:!(),'a!!':3)").unwrap(),
+        S {
+            a: 3,
+            b: None,
+            c: vec![],
+        }
+    );
+    assert_eq!(de_err::<S>("(b:!n)"), "missing field `a!` at position 6");
+}
+
+#[test]
+fn test_de_enum() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum E {
+        #[serde(rename = "a!")]
+        A,
+        B(Option<i32>),
+        C(i32, u32),
+        D {
+            a: u8,
+            b: u32,
+        },
+    }
+
+    round_trip(E::A);
+    round_trip(E::B(None));
+    round_trip(E::B(Some(2)));
+    round_trip(E::C(-1, 1));
+    round_trip(E::D { a: 1, b: 2 });
+    round_trip(vec![E::A, E::B(Some(1)), E::C(0, 0)]);
+    round_trip(hashmap! {
+        1 => E::D { a: 2, b: 3 },
+    });
+
+    assert_eq!(
+        de_err::<E>("B"),
+        "invalid type: unit variant, expected newtype variant at position 1"
+    );
+    assert_eq!(
+        de_err::<E>("(B:1,C:2)"),
+        "invalid char: 44, expected 41 at position 5"
+    );
+    assert_eq!(
+        de_err::<E>("X"),
+        "unknown variant `X`, expected one of `a!`, `B`, `C`, `D` at position 1"
+    );
+}
+
+#[test]
+fn test_de_any() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Any {
+        Unit(()),
+        Bool(bool),
+        U64(u64),
+        I64(i64),
+        F64(f64),
+        Str(String),
+        Seq(Vec<Any>),
+        Map(std::collections::BTreeMap<String, Any>),
+    }
+
+    assert_eq!(from_str::<Any>("!n").unwrap(), Any::Unit(()));
+    assert_eq!(from_str::<Any>("!t").unwrap(), Any::Bool(true));
+    assert_eq!(from_str::<Any>("!f").unwrap(), Any::Bool(false));
+    assert_eq!(from_str::<Any>("1").unwrap(), Any::U64(1));
+    assert_eq!(from_str::<Any>("-1").unwrap(), Any::I64(-1));
+    assert_eq!(from_str::<Any>("1.5").unwrap(), Any::F64(1.5));
+    assert_eq!(from_str::<Any>("1e2").unwrap(), Any::F64(100.0));
+    assert_eq!(from_str::<Any>("abc").unwrap(), Any::Str("abc".into()));
+    assert_eq!(from_str::<Any>("'a b'").unwrap(), Any::Str("a b".into()));
+    assert_eq!(
+        from_str::<Any>("!(1,a,!())").unwrap(),
+        Any::Seq(vec![Any::U64(1), Any::Str("a".into()), Any::Seq(vec![])])
+    );
+    assert_eq!(
+        from_str::<Any>("(a:!n,1:(b:!f))").unwrap(),
+        Any::Map(btreemap! {
+            "a".into() => Any::Unit(()),
+            "1".into() => Any::Map(btreemap! { "b".into() => Any::Bool(false) }),
+        })
+    );
+
+    assert_eq!(
+        de_err::<Any>("!x"),
+        "invalid escape char: 120 at position 2"
+    );
+    assert_eq!(de_err::<Any>(","), "expected value at position 1");
+    assert_eq!(
+        de_err::<Any>("!(1"),
+        "EOF while parsing a list at position 3"
+    );
+}
+
+#[test]
+fn test_de_tagged_enum() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(tag = "type")]
+    enum Internal {
+        A,
+        B { a: i32, b: Option<String> },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(tag = "t", content = "c")]
+    enum Adjacent {
+        A(u64),
+        B(Vec<i8>),
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Untagged {
+        A { a: u32 },
+        B(bool),
+        C(String),
+    }
+
+    assert_eq!(ok(&Internal::B { a: 1, b: None }), "(a:1,b:!n,type:B)");
+    round_trip(Internal::A);
+    round_trip(Internal::B {
+        a: -1,
+        b: Some("x".into()),
+    });
+    round_trip(Adjacent::A(u64::MAX));
+    round_trip(Adjacent::B(vec![-1, 1]));
+    round_trip(Untagged::A { a: 3 });
+    round_trip(Untagged::B(false));
+    round_trip(Untagged::C("I'm".into()));
+}
+
+#[test]
+fn test_de_flatten() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Inner {
+        b: i64,
+        c: String,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Outer {
+        a: bool,
+        #[serde(flatten)]
+        inner: Inner,
+        #[serde(flatten)]
+        rest: HashMap<String, Vec<u8>>,
+    }
+
+    let value = Outer {
+        a: true,
+        inner: Inner {
+            b: -3,
+            c: "c!".into(),
+        },
+        rest: hashmap! {
+            "d".into() => vec![1, 2],
+        },
+    };
+    assert_eq!(ok(&value), "(a:!t,b:-3,c:'c!!',d:!(1,2))");
+    round_trip(value);
+}
+
+#[test]
+fn test_value() {
+    let v: Value = "(a:!(1,-2,3.5,!n),b:'x y',c:(d:!t),e:18446744073709551615)"
+        .parse()
+        .unwrap();
+    assert!(v.is_object());
+    assert_eq!(v["a"][0], Value::Number(1.into()));
+    assert_eq!(v["a"][0].as_u64(), Some(1));
+    assert_eq!(v["a"][0].as_i64(), Some(1));
+    assert!(v["a"][1].is_i64());
+    assert!(!v["a"][1].is_u64());
+    assert_eq!(v["a"][1].as_i64(), Some(-2));
+    assert_eq!(v["a"][1].as_u64(), None);
+    assert!(v["a"][2].is_f64());
+    assert_eq!(v["a"][2].as_f64(), Some(3.5));
+    assert_eq!(v["a"][2].as_i64(), None);
+    assert!(v["a"][3].is_null());
+    assert_eq!(v["a"].as_array().map(Vec::len), Some(4));
+    assert_eq!(v["b"].as_str(), Some("x y"));
+    assert_eq!(v["c"]["d"].as_bool(), Some(true));
+    assert_eq!(v["e"].as_u64(), Some(u64::MAX));
+    assert!(!v["e"].is_i64());
+
+    // Missing keys, out of bounds indexes and mismatched types are null.
+    assert_eq!(v["missing"], Value::Null);
+    assert_eq!(v["a"][10], Value::Null);
+    assert_eq!(v["b"]["x"], Value::Null);
+    assert_eq!(v[0], Value::Null);
+    assert_eq!(v.get("missing"), None);
+    assert_eq!(v.get("b"), Some(&Value::String("x y".to_owned())));
+
+    assert_eq!(
+        v.to_string(),
+        "(a:!(1,-2,3.5,!n),b:'x y',c:(d:!t),e:18446744073709551615)"
+    );
+    assert_eq!(ok(&v), v.to_string());
+    assert_eq!(from_str::<Value>(&v.to_string()).unwrap(), v);
+
+    assert_eq!("!t".parse::<Value>().unwrap(), Value::Bool(true));
+    assert_eq!("''".parse::<Value>().unwrap(), Value::String(String::new()));
+    assert_eq!("!()".parse::<Value>().unwrap(), Value::Array(vec![]));
+    assert_eq!("()".parse::<Value>().unwrap(), Value::Object(Map::new()));
+    assert_eq!(
+        "(".parse::<Value>().unwrap_err().to_string(),
+        "EOF while parsing an object at position 1"
+    );
+
+    assert_eq!(Number::from_f64(f64::NAN), None);
+    assert_eq!(Number::from(-1i8).to_string(), "-1");
+    assert_eq!(Number::from_f64(1.0).unwrap().to_string(), "1.0");
+    assert_eq!(Value::from(f64::INFINITY), Value::Null);
+    assert_eq!(Value::from(vec!["a", "b"]).to_string(), "!(a,b)");
+    assert_eq!(
+        vec![("k", 1), ("j", 2)]
+            .into_iter()
+            .collect::<Value>()
+            .to_string(),
+        "(j:2,k:1)"
+    );
+
+    // `drop_deep` does not recurse.
+    let mut deep = Value::Null;
+    for i in 0..1_000_000 {
+        deep = if i % 2 == 0 {
+            Value::Array(vec![deep, Value::from(i)])
+        } else {
+            Value::Object(Map::from_iter([("a".to_owned(), deep)]))
+        };
+    }
+    deep.drop_deep();
+}
+
+#[test]
+fn test_value_index_mut() {
+    let mut v = Value::Null;
+    v["a"] = Value::from(1);
+    v["b"] = Value::from(vec![1, 2]);
+    v["b"][1] = Value::from("x");
+    v["c"]["d"] = Value::from(true);
+    assert_eq!(v.to_string(), "(a:1,b:!(1,x),c:(d:!t))");
+
+    let taken = v["a"].take();
+    assert_eq!(taken, Value::from(1));
+    assert!(v["a"].is_null());
+
+    v.as_object_mut().unwrap().remove("a");
+    v.get_mut("b")
+        .unwrap()
+        .as_array_mut()
+        .unwrap()
+        .push(Value::Null);
+    assert_eq!(v.to_string(), "(b:!(1,x,!n),c:(d:!t))");
+
+    let keys: Vec<_> = v.as_object().unwrap().keys().cloned().collect();
+    assert_eq!(keys, vec!["b", "c"]);
+}
+
+#[test]
+#[should_panic(expected = "cannot access index 2 of rison array of length 2")]
+fn test_value_index_mut_out_of_bounds() {
+    let mut v = Value::from(vec![1, 2]);
+    v[2] = Value::Null;
+}
+
+#[test]
+fn test_to_from_value() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    enum E {
+        Unit,
+        Newtype(i32),
+        Tuple(u8, String),
+        Struct { a: bool },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct S {
+        name: String,
+        page: u32,
+        ratio: f64,
+        tags: Vec<String>,
+        parent: Option<Box<S>>,
+        ids: BTreeMap<u32, i64>,
+        kinds: Vec<E>,
+        #[serde(with = "serde_bytes")]
+        raw: Vec<u8>,
+    }
+
+    let value = S {
+        name: "a b".to_owned(),
+        page: 3,
+        ratio: -0.5,
+        tags: vec!["x".to_owned()],
+        parent: None,
+        ids: btreemap! {1 => -1, 20 => 2},
+        kinds: vec![
+            E::Unit,
+            E::Newtype(-7),
+            E::Tuple(1, "t".to_owned()),
+            E::Struct { a: true },
+        ],
+        raw: vec![0, 255],
+    };
+    let v = to_value(&value).unwrap();
+    assert_eq!(v["name"], Value::from("a b"));
+    assert_eq!(v["ids"]["20"], Value::from(2));
+    assert_eq!(v["kinds"][1]["Newtype"], Value::from(-7));
+    // Map keys are strings in a `Value`, so integer keys come out quoted.
+    assert_eq!(
+        v.to_string(),
+        to_string(&value)
+            .unwrap()
+            .replace("ids:(1:-1,20:2)", "ids:('1':-1,'20':2)")
+    );
+    assert_eq!(from_value::<S>(v.clone()).unwrap(), value);
+
+    // Edit one field structurally and read the struct back.
+    let mut v = v;
+    v["page"] = Value::from(4);
+    v["parent"] = to_value(&value).unwrap();
+    let edited = from_value::<S>(v.clone()).unwrap();
+    assert_eq!(edited.page, 4);
+    assert_eq!(edited.parent.as_deref(), Some(&value));
+
+    // Errors point at the value that failed, as they do for text.
+    v["parent"]["tags"][0] = Value::from(1);
+    let err = from_value::<S>(v.clone()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "parent.tags[0]: invalid type: integer `1`, expected a string"
+    );
+    v["parent"]["tags"][0] = Value::from("x");
+    v["ids"]["20"] = Value::from("2");
+    assert_eq!(from_value::<S>(v).unwrap_err().path(), "ids.20");
+
+    assert_eq!(to_value(()).unwrap(), Value::Null);
+    assert_eq!(to_value(f64::NAN).unwrap(), Value::Null);
+    assert_eq!(
+        to_value(u128::from(u64::MAX)).unwrap(),
+        Value::from(u64::MAX)
+    );
+    assert_eq!(
+        to_value(u128::MAX).unwrap_err().to_string(),
+        "number out of range"
+    );
+    assert_eq!(
+        to_value(btreemap! {vec![1] => 1}).unwrap_err().to_string(),
+        "key must be a string, found sequence"
+    );
+
+    assert_eq!(
+        from_value::<HashMap<u8, bool>>(Value::from_iter(vec![("1", true)])).unwrap(),
+        hashmap! {1 => true}
+    );
+    assert_eq!(from_value::<Option<u8>>(Value::Null).unwrap(), None);
+    assert_eq!(from_value::<E>(Value::from("Unit")).unwrap(), E::Unit);
+    assert_eq!(
+        from_value::<u8>(Value::from(-1)).unwrap_err().to_string(),
+        "invalid value: integer `-1`, expected u8"
+    );
+    assert_eq!(
+        from_value::<String>(Value::Null).unwrap_err().to_string(),
+        "invalid type: null, expected a string"
+    );
+    assert_eq!(
+        from_value::<(u8,)>(Value::from(vec![1, 2]))
+            .unwrap_err()
+            .to_string(),
+        "invalid length 2, expected fewer elements in array"
+    );
+    assert_eq!(
+        from_value::<E>(Value::from_iter(vec![("Unit", 1), ("Newtype", 2)]))
+            .unwrap_err()
+            .to_string(),
+        "invalid value: map, expected map with a single key"
+    );
+
+    let v: Value = "!(1,2)".parse().unwrap();
+    let pair = <(u8, u8)>::deserialize(v.into_deserializer()).unwrap();
+    assert_eq!(pair, (1, 2));
+}
+
+#[test]
+fn test_rison_macro() {
+    assert_eq!(rison!(null), Value::Null);
+    assert_eq!(rison!([]), Value::Array(vec![]));
+    assert_eq!(rison!({}), Value::Object(Map::new()));
+
+    let name = "a b";
+    let key = "dyn";
+    let v = rison!({
+        "name": name,
+        "n": 1 + 2,
+        "list": [true, false, null, [1.5], {"x": -1}],
+        key: Some("k"),
+        (format!("{}{}", "x", 1)): ["it's"],
+    });
+    assert_eq!(
+        v.to_string(),
+        "(dyn:k,list:!(!t,!f,!n,!(1.5),(x:-1)),n:3,name:'a b',x1:!('it!'s'))"
+    );
+    assert_eq!(v, from_str::<Value>(&v.to_string()).unwrap());
+}
+
+#[test]
+fn test_format_rison() {
+    assert_eq!(format_rison!("(a:1)"), "(a:1)");
+
+    let q = "x' or !t";
+    assert_eq!(
+        format_rison!("(q:{},n:{},e:{})", q, -1.5, ""),
+        "(q:'x!' or !!t',n:-1.5,e:'')"
+    );
+    assert_eq!(
+        format_rison!("!({},{})", rison!({"a b": [1]}), Some(true)),
+        "!(('a b':!(1)),!t)"
+    );
+    assert_eq!(
+        format_rison!("({{x}}:{1},y:{0},z:{1})", "a'", 2),
+        "({x}:2,y:'a!'',z:2)"
+    );
+}
+
+#[test]
+fn test_ser_writer() {
+    let value = btreemap! {"a b" => vec![1, 2], "c" => vec![]};
+    let expected = "('a b':!(1,2),c:!())";
+
+    let mut buf = Vec::new();
+    to_writer(&mut buf, &value).unwrap();
+    assert_eq!(buf, expected.as_bytes());
+    assert_eq!(to_vec(&value).unwrap(), expected.as_bytes());
+
+    let mut s = String::from("q=");
+    to_fmt(&mut s, &value).unwrap();
+    assert_eq!(s, format!("q={}", expected));
+
+    let mut ser = serde_rison::ser::Serializer::new(String::new());
+    value.serialize(&mut ser).unwrap();
+    assert_eq!(ser.into_inner(), expected);
+
+    assert_eq!(format!("[{}]", display(&value)), format!("[{}]", expected));
+    assert_eq!(display("it's").to_string(), "'it!'s'");
+
+    struct FailingWriter;
+
+    impl io::Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken pipe"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let err = to_writer(FailingWriter, &value).unwrap_err();
+    assert_eq!(err.to_string(), "broken pipe");
+    assert!(std::error::Error::source(&err).is_some());
+
+    // A configured serializer over an `io::Write` keeps the io error too.
+    #[derive(Serialize)]
+    struct Dup {
+        b: u8,
+        a: u8,
+        #[serde(flatten)]
+        rest: BTreeMap<&'static str, u8>,
+    }
+
+    let mut ser = serde_rison::ser::Serializer::from_io(Vec::new())
+        .key_order(serde_rison::ser::KeyOrder::Insertion)
+        .duplicate_keys(serde_rison::DuplicateKeys::Error);
+    let dup = Dup {
+        b: 1,
+        a: 2,
+        rest: btreemap! {"b" => 3},
+    };
+    let err = ser.serialize_io(&dup).unwrap_err();
+    assert_eq!(err.to_string(), "duplicate key `b`");
+    let dup = Dup {
+        rest: btreemap! {"c" => 3},
+        ..dup
+    };
+    ser.serialize_io(&dup).unwrap();
+    assert_eq!(ser.into_inner().into_inner(), b"(b:1,a:2,c:3)");
+    let mut ser = serde_rison::ser::Serializer::from_io(FailingWriter)
+        .key_order(serde_rison::ser::KeyOrder::Insertion);
+    let err = ser.serialize_io(&value).unwrap_err();
+    assert_eq!(err.to_string(), "broken pipe");
+    assert!(std::error::Error::source(&err).is_some());
+
+    let err = to_fmt(String::new(), &hashmap! {vec![1] => 1}).unwrap_err();
+    assert_eq!(err.to_string(), "key must be a string, found sequence");
+}
+
+#[test]
+fn test_ser_nested_maps() {
+    #[derive(Serialize)]
+    struct Inner {
+        z: Vec<BTreeMap<&'static str, i32>>,
+        a: Option<Box<Inner>>,
+    }
+
+    let value = hashmap! {
+        "b" => vec![Inner {
+            z: vec![btreemap! {"y" => 1, "x" => 2}, btreemap! {}],
+            a: Some(Box::new(Inner { z: vec![], a: None })),
+        }],
+        "a b" => vec![],
+    };
+    let expected = "('a b':!(),b:!((a:(a:!n,z:!()),z:!((x:2,y:1),()))))";
+    assert_eq!(to_string(&value).unwrap(), expected);
+    assert_eq!(
+        to_string_uri(&value).unwrap(),
+        "('a+b':!(),b:!((a:(a:!n,z:!()),z:!((x:2,y:1),()))))"
+    );
+    assert_eq!(
+        to_string_orison(&value).unwrap(),
+        &expected[1..expected.len() - 1]
+    );
+
+    // The serializer can be reused once a top-level map is written out.
+    let mut ser = serde_rison::ser::Serializer::new(String::new());
+    value.serialize(&mut ser).unwrap();
+    value.serialize(&mut ser).unwrap();
+    assert_eq!(ser.into_inner(), format!("{}{}", expected, expected));
+
+    // Also after a map failed halfway through.
+    let mut ser = serde_rison::ser::Serializer::new(String::new());
+    let invalid = btreemap! {"a" => btreemap! {"b" => hashmap! {vec![1] => 1}}};
+    assert!(invalid.serialize(&mut ser).is_err());
+    value.serialize(&mut ser).unwrap();
+    vec![btreemap! {"x" => 1}].serialize(&mut ser).unwrap();
+    assert_eq!(ser.into_inner(), format!("{}!((x:1))", expected));
+
+    struct Duplicates;
+
+    impl Serialize for Duplicates {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeMap;
+            let mut map = serializer.serialize_map(None)?;
+            map.serialize_entry("b", &1)?;
+            map.serialize_entry("a", &2)?;
+            map.serialize_entry("b", &3)?;
+            map.end()
+        }
+    }
+
+    assert_eq!(to_string(&Duplicates).unwrap(), "(a:2,b:3)");
+}
+
+#[test]
+fn test_ser_key_order() {
+    use serde_rison::ser::{KeyOrder, Serializer};
+
+    #[derive(Serialize)]
+    struct S {
+        z: u8,
+        #[serde(rename = "a b")]
+        a_b: u8,
+        #[serde(rename = "!x")]
+        x: u8,
+        b: BTreeMap<&'static str, u8>,
+    }
+
+    fn ser<T: Serialize>(value: &T, order: KeyOrder) -> String {
+        let mut ser = Serializer::new(String::new()).key_order(order);
+        value.serialize(&mut ser).unwrap();
+        ser.into_inner()
+    }
+
+    let value = S {
+        z: 1,
+        a_b: 2,
+        x: 3,
+        b: btreemap! {"it's" => 4, "c" => 5},
+    };
+    assert_eq!(
+        ser(&value, KeyOrder::Escaped),
+        "('!!x':3,'a b':2,b:('it!'s':4,c:5),z:1)"
+    );
+    assert_eq!(to_string(&value).unwrap(), ser(&value, KeyOrder::Escaped));
+    assert_eq!(
+        ser(&value, KeyOrder::Raw),
+        "('!!x':3,'a b':2,b:(c:5,'it!'s':4),z:1)"
+    );
+    assert_eq!(
+        ser(&btreemap! {"a b" => 1, "a" => 2, "B" => 3}, KeyOrder::Raw),
+        "(B:3,a:2,'a b':1)"
+    );
+    assert_eq!(
+        ser(
+            &btreemap! {"a b" => 1, "a" => 2, "B" => 3},
+            KeyOrder::Escaped
+        ),
+        "('a b':1,B:3,a:2)"
+    );
+    assert_eq!(
+        ser(&value, KeyOrder::Insertion),
+        "(z:1,'a b':2,'!!x':3,b:(c:5,'it!'s':4))"
+    );
+    assert_eq!(
+        ser(
+            &value,
+            KeyOrder::Custom(|a, b| a.len().cmp(&b.len()).then(b.cmp(a)))
+        ),
+        "(z:1,b:(c:5,'it!'s':4),'!!x':3,'a b':2)"
+    );
+}
+
+#[test]
+fn test_duplicate_keys() {
+    use serde_rison::{
+        ser::{KeyOrder, Serializer},
+        DuplicateKeys,
+    };
+
+    #[derive(Serialize)]
+    struct Flattened {
+        a: u8,
+        b: u8,
+        #[serde(flatten)]
+        extra: BTreeMap<&'static str, u8>,
+    }
+
+    fn ser<T: Serialize>(value: &T, policy: DuplicateKeys, order: KeyOrder) -> String {
+        let mut ser = Serializer::new(String::new())
+            .duplicate_keys(policy)
+            .key_order(order);
+        match value.serialize(&mut ser) {
+            Ok(()) => ser.into_inner(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    let value = Flattened {
+        a: 1,
+        b: 2,
+        extra: btreemap! {"a" => 3, "c" => 4},
+    };
+    assert_eq!(to_string(&value).unwrap(), "(a:3,b:2,c:4)");
+    for (policy, sorted, insertion) in [
+        (DuplicateKeys::LastWins, "(a:3,b:2,c:4)", "(b:2,a:3,c:4)"),
+        (DuplicateKeys::FirstWins, "(a:1,b:2,c:4)", "(a:1,b:2,c:4)"),
+        (
+            DuplicateKeys::Error,
+            "duplicate key `a`",
+            "duplicate key `a`",
+        ),
+    ] {
+        assert_eq!(ser(&value, policy, KeyOrder::Escaped), sorted);
+        assert_eq!(ser(&value, policy, KeyOrder::Insertion), insertion);
+    }
+    let mut reused = Serializer::new(String::new()).duplicate_keys(DuplicateKeys::Error);
+    assert!(value.serialize(&mut reused).is_err());
+    vec![btreemap! {"x" => 1}].serialize(&mut reused).unwrap();
+    assert_eq!(reused.into_inner(), "!((x:1))");
+    // Duplicates are found even when the key order does not put them next
+    // to each other.
+    struct Pairs(&'static [(&'static str, u8)]);
+    impl Serialize for Pairs {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().copied())
+        }
+    }
+    let pairs = Pairs(&[("a", 1), ("B", 2), ("A", 3), ("a", 4)]);
+    let lowercase = KeyOrder::Custom(|a, b| a.to_lowercase().cmp(&b.to_lowercase()));
+    for (policy, expected) in [
+        (DuplicateKeys::LastWins, "(A:3,a:4,B:2)"),
+        (DuplicateKeys::FirstWins, "(a:1,A:3,B:2)"),
+        (DuplicateKeys::Error, "duplicate key `a`"),
+    ] {
+        assert_eq!(ser(&pairs, policy, lowercase), expected);
+    }
+    let quoted = hashmap! {"x" => Flattened { a: 1, b: 2, extra: btreemap! {"it's" => 3} }};
+    assert_eq!(
+        ser(&quoted, DuplicateKeys::Error, KeyOrder::Escaped),
+        "(x:('it!'s':3,a:1,b:2))"
+    );
+
+    fn de<T: DeserializeOwned>(s: &str, policy: DuplicateKeys) -> Result<T, Error> {
+        let mut de = Deserializer::from_str(s).duplicate_keys(policy);
+        T::deserialize(&mut de)
+    }
+
+    let s = "(a:1,b:(x:!t),a:2,'it!'s':3,b:!n,'it!'s':4)";
+    assert_eq!(
+        de::<Value>(s, DuplicateKeys::LastWins).unwrap(),
+        from_str::<Value>("(a:2,b:!n,'it!'s':4)").unwrap()
+    );
+    assert_eq!(
+        de::<Value>(s, DuplicateKeys::FirstWins).unwrap(),
+        from_str::<Value>("(a:1,b:(x:!t),'it!'s':3)").unwrap()
+    );
+    assert_eq!(
+        de::<Value>(s, DuplicateKeys::Error)
+            .unwrap_err()
+            .to_string(),
+        "duplicate key `a` at position 15"
+    );
+    assert_eq!(
+        de::<BTreeMap<String, u8>>("(1:1,'1':2)", DuplicateKeys::Error)
+            .unwrap_err()
+            .to_string(),
+        "duplicate key `1` at position 6"
+    );
+    assert_eq!(
+        de::<BTreeMap<i32, u8>>("(-1:1,2:2,-1:3)", DuplicateKeys::FirstWins).unwrap(),
+        btreemap! {-1 => 1, 2 => 2}
+    );
+    assert_eq!(
+        de::<BTreeMap<String, u8>>("(!t:1)", DuplicateKeys::Error)
+            .unwrap_err()
+            .to_string(),
+        "key must be a string at position 2"
+    );
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct S {
+        a: u8,
+        #[serde(default)]
+        b: Option<String>,
+    }
+
+    assert_eq!(
+        de::<S>("(a:1,b:x,a:2)", DuplicateKeys::FirstWins).unwrap(),
+        S {
+            a: 1,
+            b: Some("x".to_owned())
+        }
+    );
+    assert_eq!(
+        de::<S>("(a:1,a:2)", DuplicateKeys::LastWins)
+            .unwrap_err()
+            .to_string(),
+        "duplicate field `a` at position 7"
+    );
+}
+
+#[test]
+fn test_error_classify() {
+    use serde_rison::de::Category;
+
+    let err = from_str::<Value>("(a:!x)").unwrap_err();
+    assert_eq!(err.classify(), Category::Syntax);
+    assert!(err.is_syntax() && !err.is_data() && !err.is_eof() && !err.is_io());
+    assert_eq!(err.position(), Some(5));
+
+    let err = from_str::<Vec<u8>>("!(1,2").unwrap_err();
+    assert_eq!(err.classify(), Category::Eof);
+    assert!(err.is_eof());
+    assert_eq!(err.position(), Some(5));
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+
+    let err = from_str::<Vec<u8>>("!(1,'x')").unwrap_err();
+    assert_eq!(err.classify(), Category::Data);
+    assert_eq!(err.position(), Some(7));
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+    let err = from_value::<String>(Value::Null).unwrap_err();
+    assert!(err.is_data());
+    assert_eq!(err.position(), None);
+
+    let err = from_reader::<_, Value>(FailingReader).unwrap_err();
+    assert!(err.is_io());
+    assert_eq!(err.io_error_kind(), Some(io::ErrorKind::Other));
+
+    struct FailingReader;
+
+    impl io::Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("gone"))
+        }
+    }
+
+    // Errors raised by a `Deserialize` impl itself get the position too.
+    #[derive(Deserialize, Debug)]
+    #[serde(try_from = "u32")]
+    struct Even(#[allow(dead_code)] u32);
+
+    impl TryFrom<u32> for Even {
+        type Error = String;
+
+        fn try_from(n: u32) -> Result<Self, String> {
+            if n.is_multiple_of(2) {
+                Ok(Even(n))
+            } else {
+                Err(format!("{} is odd", n))
+            }
+        }
+    }
+
+    let err = from_str::<Even>("3").unwrap_err();
+    assert!(err.is_data());
+    assert_eq!(err.to_string(), "3 is odd at position 1");
+    let err = from_str::<Vec<Even>>("!(2,5,4)").unwrap_err();
+    assert_eq!(err.to_string(), "[1]: 5 is odd at position 6");
+}
+
+#[test]
+fn test_error_path() {
+    #[derive(Deserialize, Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Panel {
+        grid_data: GridData,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    struct GridData {
+        w: u8,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Dashboard {
+        #[allow(dead_code)]
+        panels: Vec<Panel>,
+    }
+
+    let s = "(panels:!((gridData:(w:1)),(gridData:(w:-1))))";
+    let err = from_str::<Dashboard>(s).unwrap_err();
+    assert_eq!(err.path(), "panels[1].gridData.w");
+    assert_eq!(
+        err.to_string(),
+        "panels[1].gridData.w: invalid value: integer `-1`, expected u8 at position 42"
+    );
+    assert_eq!(err.position(), Some(42));
+
+    // Keys that are not ids are quoted, and map keys are kept as parsed.
+    let err = from_str::<HashMap<String, Vec<bool>>>("('a b':!(!t,1))").unwrap_err();
+    assert_eq!(err.path(), "'a b'[1]");
+    assert_eq!(from_str::<Vec<u8>>("!(1,x)").unwrap_err().path(), "[1]");
+    let err = from_str::<HashMap<u32, Vec<u8>>>("(1:!(x))").unwrap_err();
+    assert_eq!(err.path(), "1[0]");
+    let err = from_str::<BTreeMap<i128, (u8,)>>("(-2:!(1),1:!(x))").unwrap_err();
+    assert_eq!(err.path(), "1[0]");
+    assert_eq!(from_str::<u8>("x").unwrap_err().path(), "");
+
+    let value = vec![btreemap! {
+        "a b" => vec![hashmap! { vec![1] => 1 }],
+    }];
+    let err = to_string(&value).unwrap_err();
+    assert_eq!(err.path(), "[0].'a b'[0]");
+    assert_eq!(
+        err.to_string(),
+        "[0].'a b'[0]: key must be a string, found sequence"
+    );
+    let err = to_string(&btreemap! { "x" => btreemap! { () => 1 } }).unwrap_err();
+    assert_eq!(err.to_string(), "x: key must be a string, found unit");
+    assert!(!err.is_io());
+}
+
+#[test]
+fn test_limits() {
+    use serde::de::IgnoredAny;
+
+    fn limited<T: DeserializeOwned>(
+        s: &str,
+        f: impl FnOnce(
+            Deserializer<serde_rison::read::SliceRead>,
+        ) -> Deserializer<serde_rison::read::SliceRead>,
+    ) -> Result<T, Error> {
+        T::deserialize(&mut f(Deserializer::from_str(s)))
+    }
+
+    // 128 levels are fine by default, and anything deeper is rejected
+    // before it can overflow the stack.
+    let nested = |depth| format!("{}{}", "!(".repeat(depth), ")".repeat(depth));
+    from_str::<Value>(&nested(128)).unwrap();
+    let err = from_str::<Value>(&nested(100_000)).unwrap_err();
+    assert_eq!(err.path(), "[0]".repeat(128));
+    assert_eq!(err.position(), Some(258));
+    let err = from_str::<IgnoredAny>(&"(a:".repeat(100_000)).unwrap_err();
+    assert_eq!(err.to_string(), "recursion limit exceeded at position 385");
+    let err = limited::<Value>("(a:!((b:1)))", |de| de.max_depth(2)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "a[0]: recursion limit exceeded at position 6"
+    );
+    assert_eq!(err.limit(), Some(LimitKind::Depth));
+    limited::<Value>("(a:!(1))", |de| de.max_depth(2)).unwrap();
+
+    let err = limited::<Value>("(a:abcd)", |de| de.max_string_len(3)).unwrap_err();
+    assert_eq!(err.to_string(), "a: string too long at position 4");
+    let err = limited::<Value>("('a!!bcd':x)", |de| de.max_string_len(3)).unwrap_err();
+    assert_eq!(err.to_string(), "string too long at position 2");
+    assert_eq!(err.limit(), Some(LimitKind::StringLen));
+    limited::<Value>("(a:'a!!b')", |de| de.max_string_len(3)).unwrap();
+
+    let err = limited::<Vec<u8>>("!(1,2,3)", |de| de.max_collection_len(2)).unwrap_err();
+    assert_eq!(err.to_string(), "too many elements at position 7");
+    let err = limited::<Value>("(a:1,b:2,c:3)", |de| de.max_collection_len(2)).unwrap_err();
+    assert_eq!(err.to_string(), "too many elements at position 10");
+    let err = limited::<IgnoredAny>("!(1,2,3)", |de| de.max_collection_len(2)).unwrap_err();
+    assert_eq!(err.to_string(), "too many elements at position 7");
+    assert_eq!(err.limit(), Some(LimitKind::CollectionLen));
+    limited::<Vec<u8>>("!(1,2)", |de| de.max_collection_len(2)).unwrap();
+
+    let err = limited::<Vec<u8>>("!(1,2,3)", |de| de.max_input_len(5)).unwrap_err();
+    assert_eq!(err.to_string(), "[1]: input too long at position 6");
+    let err = limited::<String>("'abcdef'", |de| de.max_input_len(5)).unwrap_err();
+    assert_eq!(err.to_string(), "input too long at position 1");
+    limited::<Vec<u8>>("!(1,2)", |de| de.max_input_len(6)).unwrap();
+
+    // Limit errors are syntax errors that tell which limit was hit.
+    assert!(err.is_syntax());
+    assert_eq!(err.limit(), Some(LimitKind::InputLen));
+    let err = limited::<Vec<u8>>("!(1,2", |de| de.max_input_len(6)).unwrap_err();
+    assert!(err.is_eof());
+    assert_eq!(err.limit(), None);
+
+    // Readers stop as soon as a string goes over a limit, rather than
+    // buffering all of it first.
+    struct Endless<'a>(&'a mut usize);
+    impl io::Read for Endless<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf[0] = if *self.0 == 0 { b'\'' } else { b'a' };
+            *self.0 += 1;
+            Ok(1)
+        }
+    }
+    let mut read = 0;
+    let err =
+        String::deserialize(&mut Deserializer::from_reader(Endless(&mut read)).max_string_len(10))
+            .unwrap_err();
+    assert_eq!(err.to_string(), "string too long at position 1");
+    assert_eq!(read, 12);
+    let mut read = 0;
+    let err =
+        String::deserialize(&mut Deserializer::from_reader(Endless(&mut read)).max_input_len(20))
+            .unwrap_err();
+    assert_eq!(err.to_string(), "input too long at position 1");
+    assert_eq!(read, 22);
+}
+
+#[cfg(feature = "unbounded_depth")]
+#[test]
+fn test_unbounded_depth() {
+    use serde::{
+        de::{IgnoredAny, MapAccess, SeqAccess, Visitor},
+        ser::{SerializeMap, SerializeSeq},
+    };
+    use std::fmt;
+
+    const DEPTH: usize = 100_000;
+
+    // Nested sequences, maps or variants without building a tree, whose drop
+    // would overflow the stack on its own.
+    #[derive(Clone, Copy)]
+    enum Nested {
+        Seq(usize),
+        Map(usize),
+        Variant(usize),
+    }
+
+    impl Serialize for Nested {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match *self {
+                Nested::Seq(0) | Nested::Map(0) | Nested::Variant(0) => serializer.serialize_unit(),
+                Nested::Seq(depth) => {
+                    let mut seq = serializer.serialize_seq(Some(1))?;
+                    seq.serialize_element(&Nested::Seq(depth - 1))?;
+                    seq.end()
+                }
+                Nested::Map(depth) => {
+                    let mut map = serializer.serialize_map(Some(1))?;
+                    map.serialize_entry("a", &Nested::Map(depth - 1))?;
+                    map.end()
+                }
+                Nested::Variant(depth) => serializer.serialize_newtype_variant(
+                    "Expr",
+                    0,
+                    "not",
+                    &Nested::Variant(depth - 1),
+                ),
+            }
+        }
+    }
+
+    // How deep the input is nested.
+    struct Depth(usize);
+
+    impl<'de> Deserialize<'de> for Depth {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct DepthVisitor;
+
+            impl<'de> Visitor<'de> for DepthVisitor {
+                type Value = Depth;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("nested sequences or maps")
+                }
+
+                fn visit_unit<E>(self) -> Result<Depth, E> {
+                    Ok(Depth(0))
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Depth, A::Error> {
+                    let Depth(depth) = seq.next_element()?.unwrap_or(Depth(0));
+                    Ok(Depth(depth + 1))
+                }
+
+                fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Depth, A::Error> {
+                    let Depth(depth) = match map.next_key::<IgnoredAny>()? {
+                        Some(_) => map.next_value()?,
+                        None => Depth(0),
+                    };
+                    Ok(Depth(depth + 1))
+                }
+            }
+
+            deserializer.deserialize_any(DepthVisitor)
+        }
+    }
+
+    for (nested, open, close) in [
+        (Nested::Seq(DEPTH), "!(", ")"),
+        (Nested::Map(DEPTH), "(a:", ")"),
+        (Nested::Variant(DEPTH), "(not:", ")"),
+    ] {
+        let mut ser = serde_rison::ser::Serializer::new(String::new()).grow_stack();
+        nested.serialize(&mut ser).unwrap();
+        let s = ser.into_inner();
+        assert_eq!(
+            s,
+            format!("{}!n{}", open.repeat(DEPTH), close.repeat(DEPTH))
+        );
+
+        let mut de = Deserializer::from_str(&s).disable_recursion_limit();
+        assert_eq!(Depth::deserialize(&mut de).unwrap().0, DEPTH);
+        let mut de = Deserializer::from_str(&s).disable_recursion_limit();
+        IgnoredAny::deserialize(&mut de).unwrap();
+
+        // `Value` grows the stack itself, so the convenience functions
+        // handle it too.
+        let mut de = Deserializer::from_str(&s).disable_recursion_limit();
+        let value = Value::deserialize(&mut de).unwrap();
+        assert_eq!(value.to_string(), s);
+        assert_eq!(to_string(&value).unwrap(), s);
+        let mut fmt = String::new();
+        to_fmt(&mut fmt, &value).unwrap();
+        assert_eq!(fmt, s);
+        assert_eq!(serde_rison::display(&value).to_string(), s);
+        let converted = to_value(&value).unwrap();
+        assert_eq!(Depth::deserialize(converted).unwrap().0, DEPTH);
+        value.drop_deep();
+        let converted = to_value(nested).unwrap();
+        assert_eq!(converted.to_string(), s);
+        converted.drop_deep();
+    }
+}
+
+#[test]
+fn test_de_trailing() {
+    assert_eq!(
+        de_err::<bool>("!t garbage"),
+        "trailing characters at position 3"
+    );
+    assert_eq!(de_err::<u8>("1)"), "trailing characters at position 2");
+    assert_eq!(
+        de_err::<Value>("(a:1))"),
+        "trailing characters at position 6"
+    );
+    assert_eq!(
+        from_url_encoded_str::<bool>("!t%29")
+            .unwrap_err()
+            .to_string(),
+        "trailing characters at position 5"
+    );
+    assert!(from_reader::<_, bool>("!t!f".as_bytes()).is_err());
+
+    let mut de = Deserializer::from_str("!t!f");
+    assert!(bool::deserialize(&mut de).unwrap());
+    assert_eq!(
+        de.end().unwrap_err().to_string(),
+        "trailing characters at position 3"
+    );
+}
+
+#[test]
+fn test_stream() {
+    use serde_rison::ser::to_writer_lines;
+
+    let input = "1\n!t\r\n\n(a:!(x,'y z'))\n'x\ny'\n";
+    let mut stream = Deserializer::from_str(input).into_iter::<Value>();
+    assert_eq!(stream.next().unwrap().unwrap(), rison!(1));
+    assert_eq!(stream.byte_offset(), 1);
+    assert_eq!(stream.next().unwrap().unwrap(), rison!(true));
+    assert_eq!(stream.next().unwrap().unwrap(), rison!({"a": ["x", "y z"]}));
+    assert_eq!(stream.next().unwrap().unwrap(), rison!("x\ny"));
+    assert!(stream.next().is_none());
+    assert_eq!(stream.byte_offset(), input.len());
+
+    // Values borrow from the input where they can.
+    let values: Vec<&str> = Deserializer::from_str("'a'\nb")
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values, ["a", "b"]);
+    assert!(Deserializer::from_str("")
+        .into_iter::<Value>()
+        .next()
+        .is_none());
+
+    // Top-level ids end at the line break, nested ones do not.
+    let values: Vec<Value> = Deserializer::from_str("a\nb\r\n(c:d\ne)\n")
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values, [rison!("a"), rison!("b"), rison!({"c": "d\ne"})]);
+
+    // The first error ends the stream.
+    let mut stream = Deserializer::from_str("1\n2 3\n4").into_iter::<u8>();
+    assert_eq!(stream.next().unwrap().unwrap(), 1);
+    assert_eq!(
+        stream.next().unwrap().unwrap_err().to_string(),
+        "trailing characters at position 4"
+    );
+    assert!(stream.next().is_none());
+
+    let values = vec![
+        rison!("id"),
+        rison!("x\ny"),
+        rison!(""),
+        rison!({"a": [1, "b\rc"], "d\ne": "f"}),
+        rison!(null),
+        rison!(-1.5),
+    ];
+    let mut out = Vec::new();
+    to_writer_lines(&mut out, &values).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out, "id\n'x\ny'\n''\n('d\ne':f,a:!(1,'b\rc'))\n!n\n-1.5\n");
+    let stream = Deserializer::from_reader(out.as_bytes()).into_iter::<Value>();
+    assert_eq!(stream.collect::<Result<Vec<_>, _>>().unwrap(), values);
+}
+
+#[test]
+fn test_ignore_whitespace() {
+    fn lenient<'de, T: Deserialize<'de>>(s: &'de str) -> Result<T, Error> {
+        let mut de = Deserializer::from_str(s).ignore_whitespace();
+        let value = T::deserialize(&mut de)?;
+        de.end()?;
+        Ok(value)
+    }
+
+    let s = "(\n  a: !( 1, -2.5 ),\n\tb: 'x y' ,\r\n  c: foo\n, d : !t,\n  e: ( )\n)\n";
+    assert_eq!(
+        lenient::<Value>(s).unwrap(),
+        rison!({"a": [1, -2.5], "b": "x y", "c": "foo", "d": true, "e": {}})
+    );
+    assert_eq!(de_err::<Value>("( a:1)"), "expected value at position 2");
+
+    // Quoted strings keep their whitespace, and ids end at it.
+    assert_eq!(lenient::<String>(" ' a\n' ").unwrap(), " a\n");
+    assert_eq!(lenient::<String>("a\tb\n").unwrap(), "a\tb");
+    assert_eq!(lenient::<Vec<&str>>("!( a\n, b )").unwrap(), ["a", "b"]);
+    assert_eq!(
+        lenient::<ByteBuf>("ab\r\n").unwrap(),
+        ByteBuf::from(b"ab".to_vec())
+    );
+
+    // Tokens are not split.
+    assert!(lenient::<bool>("! t").is_err());
+    assert!(lenient::<Vec<u8>>("! (1)").is_err());
+    assert_eq!(
+        lenient::<Value>("1 2").unwrap_err().to_string(),
+        "trailing characters at position 3"
+    );
+    assert!(lenient::<i8>("- 1").is_err());
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    enum E {
+        V(u8, String),
+        S { a: Option<u8> },
+    }
+    assert_eq!(
+        lenient::<Vec<E>>("!( ( V : !( 1 , x ) ) ,\n (S: (a: !n)) )").unwrap(),
+        [E::V(1, "x".to_owned()), E::S { a: None }]
+    );
+
+    let stream = Deserializer::from_str("1  \n (a: 2)\n\n!t")
+        .ignore_whitespace()
+        .into_iter::<Value>();
+    assert_eq!(
+        stream.collect::<Result<Vec<_>, _>>().unwrap(),
+        [rison!(1), rison!({"a": 2}), rison!(true)]
+    );
+}